@@ -1,5 +1,5 @@
 use clap::{Parser, Subcommand};
-use glyph::{in_memory_inputs, Input, Options};
+use glyph::{in_memory_inputs, ClapCompleter, Input, Options};
 
 #[derive(Parser, Debug)]
 #[command(name = "clap")]
@@ -21,7 +21,9 @@ fn main() -> std::io::Result<()> {
         .author("Yo Eight")
         .version("1.2.3")
         .date("July, 28th 2023")
-        .command_prompt("run");
+        .command_prompt("run")
+        .completer(ClapCompleter::<Cli>::new().command_prompt("run"))
+        .return_parse_errors();
 
     let mut inputs = in_memory_inputs(options)?;
 
@@ -34,6 +36,11 @@ fn main() -> std::io::Result<()> {
             Input::Command(c) => {
                 println!(">>> {:?}", c)
             }
+            Input::Error { line, error } => {
+                println!("!!! '{}' is not a valid command", line);
+                println!("{}", error);
+            }
+            Input::Timeout => {}
         }
     }
 
@@ -0,0 +1,32 @@
+use glyph::{in_memory_inputs, Input, Options};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+fn now() -> String {
+    let secs = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or_default();
+
+    format!(
+        "[{:02}:{:02}:{:02}]>",
+        secs / 3600 % 24,
+        secs / 60 % 60,
+        secs % 60
+    )
+}
+
+fn main() -> std::io::Result<()> {
+    let options = Options::default().live_prompt(Duration::from_secs(1), now);
+
+    let mut inputs = in_memory_inputs(options)?;
+
+    while let Some(input) = inputs.next_input()? {
+        if let Input::Exit = input {
+            break;
+        }
+
+        println!(">>> {:?}", input);
+    }
+
+    Ok(())
+}
@@ -0,0 +1,54 @@
+use glyph::{in_memory_inputs, Input, Options};
+use std::str::FromStr;
+
+#[derive(Debug)]
+enum Command {
+    Add(i64, i64),
+    Neg(i64),
+}
+
+impl FromStr for Command {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut words = s.split_whitespace();
+        let name = words.next().unwrap_or_default();
+        let mut operand = || -> Result<i64, String> {
+            let word = words
+                .next()
+                .ok_or_else(|| format!("'{}' is missing an operand", name))?;
+
+            word.parse::<i64>()
+                .map_err(|e| format!("invalid operand '{}': {}", word, e))
+        };
+
+        match name {
+            "add" => Ok(Command::Add(operand()?, operand()?)),
+            "neg" => Ok(Command::Neg(operand()?)),
+            _ => Err(format!("unknown command '{}'", name)),
+        }
+    }
+}
+
+fn main() -> std::io::Result<()> {
+    let options = Options::default()
+        .author("Yo Eight")
+        .version("1.2.3")
+        .date("July, 28th 2023")
+        .disable_free_expression();
+
+    let mut inputs = in_memory_inputs(options)?;
+
+    while let Some(input) = inputs.next_input_parsed::<Command>()? {
+        match input {
+            Input::Exit => break,
+            Input::String(s) => println!(">>> {:?}", s),
+            Input::Command(Command::Add(a, b)) => println!(">>> {}", a + b),
+            Input::Command(Command::Neg(a)) => println!(">>> {}", -a),
+            Input::Error { error, .. } => println!("{}", error),
+            Input::Timeout => {}
+        }
+    }
+
+    Ok(())
+}
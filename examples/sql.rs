@@ -0,0 +1,56 @@
+use glyph::{in_memory_inputs, Input, Options};
+use std::str::FromStr;
+
+#[derive(Debug)]
+struct Statement {
+    verb: String,
+    body: String,
+}
+
+impl FromStr for Statement {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let s = s
+            .trim()
+            .strip_suffix(';')
+            .ok_or_else(|| "a statement must end with ';'".to_string())?;
+        let (verb, body) = s.split_once(' ').unwrap_or((s, ""));
+
+        match verb.to_uppercase().as_str() {
+            "SELECT" | "INSERT" | "UPDATE" | "DELETE" => Ok(Statement {
+                verb: verb.to_uppercase(),
+                body: body.trim().to_string(),
+            }),
+            _ => Err(format!("unsupported statement '{}'", verb)),
+        }
+    }
+}
+
+fn main() -> std::io::Result<()> {
+    let options = Options::default()
+        .prompt("sql>")
+        .disable_free_expression()
+        .continuation("  ->", |text| text.trim_end().ends_with(';'))
+        .line_validator(|line| {
+            if line.matches('\'').count() % 2 == 0 {
+                Ok(())
+            } else {
+                Err("unterminated string literal".to_string())
+            }
+        });
+
+    let mut inputs = in_memory_inputs(options)?;
+
+    while let Some(input) = inputs.next_input_parsed::<Statement>()? {
+        match input {
+            Input::Exit => break,
+            Input::String(s) => println!(">>> {:?}", s),
+            Input::Command(stmt) => println!(">>> {} [{}]", stmt.verb, stmt.body),
+            Input::Error { error, .. } => println!("{}", error),
+            Input::Timeout => {}
+        }
+    }
+
+    Ok(())
+}
@@ -0,0 +1,473 @@
+use crate::width::display_width;
+use clap::{Command, Parser};
+use std::marker::PhantomData;
+use std::ops::Range;
+
+/// A candidate returned by a `Completer`.
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct Completion {
+    /// Byte range of the line this candidate replaces.
+    pub span: Range<usize>,
+    /// Text written in place of `span`.
+    pub replacement: String,
+    /// How the candidate is shown when several of them are listed.
+    pub display: String,
+}
+
+impl Completion {
+    /// Replaces `span` with `replacement`, which is also how the candidate gets shown.
+    pub fn new(span: Range<usize>, replacement: impl AsRef<str>) -> Self {
+        let replacement = replacement.as_ref().to_string();
+
+        Self {
+            span,
+            display: replacement.clone(),
+            replacement,
+        }
+    }
+
+    /// Shows the candidate as `display` in listings instead of its replacement.
+    pub fn display(self, display: impl AsRef<str>) -> Self {
+        Self {
+            display: display.as_ref().to_string(),
+            ..self
+        }
+    }
+}
+
+/// How Tab shows the candidates when there are several of them.
+#[derive(Debug, Default, Clone, Copy, Eq, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(rename_all = "snake_case"))]
+pub enum CompletionStyle {
+    /// Lists every candidate below the prompt.
+    #[default]
+    Menu,
+    /// Replaces the token with each candidate in turn, on every Tab press.
+    Cycle,
+}
+
+/// Source of the candidates Tab completes the line with.
+pub trait Completer {
+    /// Candidates for the token under the cursor. `line` is the whole input and `cursor` the byte
+    /// offset of the cursor within it. Each returned candidate says which part of the line it
+    /// replaces, so completing a token in the middle of the line keeps whatever comes after it.
+    fn complete(&self, line: &str, cursor: usize) -> Vec<Completion>;
+
+    /// Every candidate for the token under the cursor, whether or not it starts with what was
+    /// typed, used by fuzzy search modes to do the filtering themselves. Completers filtering in
+    /// `complete` should return the candidates they would have filtered out as well.
+    fn complete_unfiltered(&self, line: &str, cursor: usize) -> Vec<Completion> {
+        self.complete(line, cursor)
+    }
+}
+
+/// Completes the token under the cursor from a fixed list of words, the ones starting with what
+/// was typed of it so far.
+#[derive(Debug, Default, Clone)]
+pub struct StaticCompleter {
+    words: Vec<String>,
+}
+
+impl StaticCompleter {
+    /// Completes from `words`, listed in that order.
+    pub fn new<I, S>(words: I) -> Self
+    where
+        I: IntoIterator<Item = S>,
+        S: AsRef<str>,
+    {
+        Self {
+            words: words.into_iter().map(|w| w.as_ref().to_string()).collect(),
+        }
+    }
+}
+
+impl Completer for StaticCompleter {
+    fn complete(&self, line: &str, cursor: usize) -> Vec<Completion> {
+        let span = token_span(line, cursor);
+        let typed = &line[span.start..cursor.clamp(span.start, span.end)];
+
+        self.complete_unfiltered(line, cursor)
+            .into_iter()
+            .filter(|candidate| candidate.replacement.starts_with(typed))
+            .collect()
+    }
+
+    fn complete_unfiltered(&self, line: &str, cursor: usize) -> Vec<Completion> {
+        let span = token_span(line, cursor);
+
+        self.words
+            .iter()
+            .map(|word| Completion::new(span.clone(), word))
+            .collect()
+    }
+}
+
+/// Completes the subcommands and long flags of the clap parser `P`, for commands typed after the
+/// command prefix, `:` unless told otherwise.
+pub struct ClapCompleter<P> {
+    prefix: String,
+    parser: PhantomData<fn() -> P>,
+}
+
+impl<P: Parser> ClapCompleter<P> {
+    /// Completes commands starting with `:`.
+    pub fn new() -> Self {
+        Self {
+            prefix: ":".to_string(),
+            parser: PhantomData,
+        }
+    }
+
+    /// The prefix commands start with, to match `Options::command_prompt`. Leave it empty when
+    /// free expressions are disabled.
+    pub fn command_prompt(self, prefix: impl AsRef<str>) -> Self {
+        Self {
+            prefix: prefix.as_ref().to_string(),
+            ..self
+        }
+    }
+}
+
+impl<P: Parser> Default for ClapCompleter<P> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<P: Parser> Completer for ClapCompleter<P> {
+    fn complete(&self, line: &str, cursor: usize) -> Vec<Completion> {
+        let cursor = cursor.min(line.len());
+
+        self.complete_unfiltered(line, cursor)
+            .into_iter()
+            .filter(|candidate| {
+                let typed = &line[candidate.span.start..cursor];
+                candidate.replacement.starts_with(typed)
+            })
+            .collect()
+    }
+
+    fn complete_unfiltered(&self, line: &str, cursor: usize) -> Vec<Completion> {
+        let cursor = cursor.min(line.len());
+        let before = line[..cursor].trim_start();
+
+        let args = match before.strip_prefix(self.prefix.as_str()) {
+            Some(args) => args,
+            None => return Vec::new(),
+        };
+
+        // The token under the cursor never reaches back into the prefix, `:con` completing `con`.
+        let args_start = cursor - args.len();
+        let mut span = token_span(line, cursor);
+        span.start = span.start.max(args_start);
+        let typed = &line[span.start..cursor];
+
+        let mut command = P::command();
+        command.build();
+
+        // Words typed before the token lead to the subcommand being completed, flags and their
+        // values being skipped along the way.
+        for word in line[args_start..span.start].split_whitespace() {
+            if let Some(sub) = find_subcommand(&command, word) {
+                command = sub.clone();
+            }
+        }
+
+        let mut candidates = Vec::new();
+
+        if !typed.starts_with('-') {
+            candidates.extend(
+                command
+                    .get_subcommands()
+                    .filter(|sub| !sub.is_hide_set())
+                    .map(|sub| sub.get_name().to_string()),
+            );
+        }
+
+        if candidates.is_empty() || typed.starts_with('-') {
+            candidates.extend(
+                command
+                    .get_arguments()
+                    .filter(|arg| !arg.is_hide_set())
+                    .filter_map(|arg| arg.get_long())
+                    .map(|long| format!("--{}", long)),
+            );
+        }
+
+        candidates
+            .into_iter()
+            .map(|candidate| Completion::new(span.clone(), candidate))
+            .collect()
+    }
+}
+
+fn find_subcommand<'a>(command: &'a Command, name: &str) -> Option<&'a Command> {
+    command
+        .get_subcommands()
+        .find(|sub| sub.get_name() == name || sub.get_all_aliases().any(|alias| alias == name))
+}
+
+/// Byte range of the whitespace-delimited token the cursor is on. When the cursor sits in the
+/// middle of a token, the range covers the whole token, not only the part before the cursor.
+pub fn token_span(line: &str, cursor: usize) -> Range<usize> {
+    let cursor = cursor.min(line.len());
+    let start = line[..cursor]
+        .rfind(char::is_whitespace)
+        .map(|idx| idx + line[idx..].chars().next().map_or(1, char::len_utf8))
+        .unwrap_or(0);
+    let end = line[cursor..]
+        .find(char::is_whitespace)
+        .map(|idx| cursor + idx)
+        .unwrap_or(line.len());
+
+    start..end
+}
+
+// When every candidate replaces the same span, the longest prefix they share, provided it extends
+// what has been typed so far.
+pub(crate) fn common_prefix(line: &str, candidates: &[Completion]) -> Option<Completion> {
+    let first = candidates.first()?;
+
+    if candidates.iter().any(|c| c.span != first.span) {
+        return None;
+    }
+
+    let mut prefix = first.replacement.as_str();
+
+    for candidate in &candidates[1..] {
+        let len = prefix
+            .char_indices()
+            .zip(candidate.replacement.chars())
+            .find(|((_, a), b)| a != b)
+            .map_or(
+                prefix.len().min(candidate.replacement.len()),
+                |((idx, _), _)| idx,
+            );
+
+        prefix = &prefix[..len];
+    }
+
+    let typed = line.get(first.span.clone())?;
+
+    if prefix.len() > typed.len() && prefix.starts_with(typed) {
+        Some(Completion::new(first.span.clone(), prefix))
+    } else {
+        None
+    }
+}
+
+// Lays `items` out in columns filled top to bottom, like `ls` does, using as few rows as `width`
+// allows. A candidate wider than the terminal ends up alone in a single column and simply wraps.
+pub(crate) fn columns(items: &[&str], width: usize) -> Vec<String> {
+    const GAP: usize = 2;
+
+    if items.is_empty() {
+        return Vec::new();
+    }
+
+    let widths = items
+        .iter()
+        .map(|item| display_width(item))
+        .collect::<Vec<_>>();
+
+    let column_widths = |rows: usize| {
+        widths
+            .chunks(rows)
+            .map(|column| column.iter().copied().max().unwrap_or(0))
+            .collect::<Vec<_>>()
+    };
+
+    let rows = (1..items.len())
+        .find(|&rows| {
+            let columns = column_widths(rows);
+            columns.iter().sum::<usize>() + GAP * (columns.len() - 1) <= width
+        })
+        .unwrap_or(items.len());
+
+    let columns = column_widths(rows);
+
+    (0..rows)
+        .map(|row| {
+            let mut line = String::new();
+
+            for (column, column_width) in columns.iter().enumerate() {
+                let idx = column * rows + row;
+
+                if idx >= items.len() {
+                    break;
+                }
+
+                line.push_str(items[idx]);
+
+                if idx + rows < items.len() {
+                    let padding = column_width - widths[idx] + GAP;
+                    line.push_str(&" ".repeat(padding));
+                }
+            }
+
+            line
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use clap::CommandFactory;
+
+    fn replacements(candidates: Vec<Completion>) -> Vec<String> {
+        candidates.into_iter().map(|c| c.replacement).collect()
+    }
+
+    #[test]
+    fn unfiltered_candidates_ignore_what_was_typed() {
+        let completer = StaticCompleter::new(["grep", "git"]);
+
+        assert_eq!(replacements(completer.complete("gi", 2)), ["git"]);
+        assert_eq!(
+            replacements(completer.complete_unfiltered("gi", 2)),
+            ["grep", "git"]
+        );
+    }
+
+    #[test]
+    fn static_candidates_start_with_the_token_under_the_cursor() {
+        let completer = StaticCompleter::new(["cat", "car", "cab", "dog"]);
+        let candidates = completer.complete("x ca y", 4);
+
+        assert_eq!(replacements(candidates.clone()), ["cat", "car", "cab"]);
+        assert!(candidates.iter().all(|c| c.span == (2..4)));
+    }
+
+    #[test]
+    fn the_common_prefix_extends_the_token() {
+        let completer = StaticCompleter::new(["cat", "car", "cab"]);
+
+        let prefix = common_prefix("x c", &completer.complete("x c", 3)).unwrap();
+        assert_eq!((prefix.span, prefix.replacement.as_str()), (2..3, "ca"));
+
+        // Nothing left to extend.
+        assert_eq!(common_prefix("x ca", &completer.complete("x ca", 4)), None);
+    }
+
+    #[test]
+    fn no_common_prefix_across_different_spans() {
+        let candidates = [Completion::new(0..1, "cat"), Completion::new(2..3, "car")];
+
+        assert_eq!(common_prefix("c c", &candidates), None);
+        assert_eq!(common_prefix("c", &[]), None);
+    }
+
+    #[derive(Parser)]
+    #[command(name = "clap")]
+    #[allow(dead_code)]
+    struct Cli {
+        #[command(subcommand)]
+        command: Commands,
+    }
+
+    #[derive(clap::Subcommand)]
+    #[allow(dead_code)]
+    enum Commands {
+        /// Connect to something
+        #[command(alias = "cn")]
+        Connect {
+            node: String,
+            #[arg(long)]
+            timeout: Option<u64>,
+            #[arg(long, hide = true)]
+            trace: bool,
+        },
+        Config {
+            #[command(subcommand)]
+            action: ConfigAction,
+        },
+        #[command(hide = true)]
+        Debug,
+    }
+
+    #[derive(clap::Subcommand)]
+    #[allow(dead_code)]
+    enum ConfigAction {
+        Get,
+        Set {
+            #[arg(long)]
+            global: bool,
+        },
+    }
+
+    #[test]
+    fn clap_completes_subcommand_names_after_the_prefix() {
+        let completer = ClapCompleter::<Cli>::new();
+        let candidates = completer.complete(":con", 4);
+
+        assert_eq!(replacements(candidates.clone()), ["connect", "config"]);
+        assert!(candidates.iter().all(|c| c.span == (1..4)));
+        assert!(completer.complete("con", 3).is_empty());
+    }
+
+    #[test]
+    fn clap_completes_the_flags_of_a_known_subcommand() {
+        let completer = ClapCompleter::<Cli>::new();
+
+        assert_eq!(
+            replacements(completer.complete(":connect --t", 12)),
+            ["--timeout"]
+        );
+        assert_eq!(
+            replacements(completer.complete(":connect ", 9)),
+            ["--timeout", "--help"]
+        );
+    }
+
+    #[test]
+    fn clap_follows_the_command_prompt() {
+        let completer = ClapCompleter::<Cli>::new().command_prompt("run");
+
+        assert_eq!(replacements(completer.complete("run conn", 8)), ["connect"]);
+        assert!(completer.complete(":conn", 5).is_empty());
+    }
+
+    #[test]
+    fn aliases_lead_to_their_subcommand() {
+        let mut command = Cli::command();
+        command.build();
+
+        assert_eq!(
+            find_subcommand(&command, "cn").map(Command::get_name),
+            Some("connect")
+        );
+        assert_eq!(
+            find_subcommand(&command, "connect").map(Command::get_name),
+            Some("connect")
+        );
+        assert!(find_subcommand(&command, "cnx").is_none());
+
+        let completer = ClapCompleter::<Cli>::new();
+        assert_eq!(
+            replacements(completer.complete(":cn --t", 7)),
+            ["--timeout"]
+        );
+    }
+
+    #[test]
+    fn hidden_subcommands_and_flags_are_left_out() {
+        let completer = ClapCompleter::<Cli>::new();
+
+        assert!(completer.complete(":deb", 4).is_empty());
+        assert!(completer.complete(":connect --tr", 13).is_empty());
+        assert!(!replacements(completer.complete(":", 1)).contains(&"debug".to_string()));
+    }
+
+    #[test]
+    fn nested_subcommands_get_completed() {
+        let completer = ClapCompleter::<Cli>::new();
+
+        assert_eq!(replacements(completer.complete(":config s", 9)), ["set"]);
+        assert_eq!(
+            replacements(completer.complete(":config set --g", 15)),
+            ["--global"]
+        );
+    }
+}
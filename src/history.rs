@@ -1,87 +1,411 @@
-use crate::persistence::{FileBackend, Noop, Persistence};
+use crate::persistence::{FileBackend, HistoryErrorPolicy, Noop, Persistence};
+use std::collections::VecDeque;
 use std::io;
 use std::path::Path;
+use std::time::{Duration, SystemTime};
+
+type OnPush = dyn FnMut(&str) + Send;
+
+/// A line stored in the history, along with how running it went when that's known.
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct HistoryEntry {
+    pub line: String,
+    pub meta: Option<EntryMeta>,
+    /// When the line got stored, if the backend keeps track of it.
+    pub timestamp: Option<SystemTime>,
+}
+
+impl HistoryEntry {
+    pub fn new(line: impl Into<String>) -> Self {
+        Self {
+            line: line.into(),
+            meta: None,
+            timestamp: None,
+        }
+    }
+}
+
+/// How running a history entry went, as reported by the application.
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub struct EntryMeta {
+    pub duration: Duration,
+    pub success: bool,
+}
 
-#[derive(Debug)]
 pub struct History<A> {
-    entries: Vec<String>,
+    entries: VecDeque<HistoryEntry>,
+    limit: Option<usize>,
     offset: usize,
+    wrap: bool,
+    erase_dups: bool,
     persistence: A,
+    on_push: Option<Box<OnPush>>,
+}
+
+pub fn in_memory_history(limit: Option<usize>) -> io::Result<History<Noop>> {
+    History::new(Noop, limit)
 }
 
-pub fn in_memory_history() -> io::Result<History<Noop>> {
-    History::new(Noop)
+pub fn file_backed_history(
+    path: impl AsRef<Path>,
+    policy: HistoryErrorPolicy,
+    limit: Option<usize>,
+) -> io::Result<History<FileBackend>> {
+    let backend = FileBackend::with_policy(path, policy)?;
+    History::new(backend, limit)
 }
 
-pub fn file_backed_history(path: impl AsRef<Path>) -> io::Result<History<FileBackend>> {
-    let backend = FileBackend::new(path)?;
-    History::new(backend)
+#[cfg(feature = "jsonl")]
+pub fn jsonl_backed_history(
+    path: impl AsRef<Path>,
+    policy: HistoryErrorPolicy,
+    limit: Option<usize>,
+) -> io::Result<History<crate::persistence::JsonlBackend>> {
+    let backend = crate::persistence::JsonlBackend::with_policy(path, policy)?;
+    History::new(backend, limit)
 }
 
 impl<A: Persistence> History<A> {
-    pub fn new(mut persistence: A) -> io::Result<Self> {
-        let entries = persistence.load()?;
-        let offset = entries.len();
+    // Keeps at most `limit` entries, the oldest ones being dropped first. Loading more than that
+    // truncates the persisted entries right away.
+    pub fn new(mut persistence: A, limit: Option<usize>) -> io::Result<Self> {
+        let entries = VecDeque::from(persistence.load()?);
+        let loaded = entries.len();
 
-        Ok(History {
+        let mut history = History {
             entries,
-            offset,
+            limit,
+            offset: 0,
+            wrap: false,
+            erase_dups: false,
             persistence,
-        })
+            on_push: None,
+        };
+
+        history.evict();
+        history.reset_cursor();
+
+        if history.entries.len() < loaded {
+            history
+                .persistence
+                .persist(history.entries.make_contiguous())?;
+        }
+
+        Ok(history)
     }
 
     pub fn push(&mut self, entry: String) -> io::Result<()> {
-        if self.entries.last() != Some(&entry) {
-            self.entries.push(entry);
-            self.persistence.persist(&self.entries)?;
+        if self.entries.back().map(|last| &last.line) != Some(&entry) {
+            if self.erase_dups {
+                self.entries.retain(|older| older.line != entry);
+            }
+
+            self.entries.push_back(HistoryEntry {
+                timestamp: Some(SystemTime::now()),
+                ..HistoryEntry::new(entry)
+            });
+            self.evict();
+            self.persistence.persist(self.entries.make_contiguous())?;
+
+            if let (Some(on_push), Some(entry)) = (self.on_push.as_mut(), self.entries.back()) {
+                on_push(&entry.line);
+            }
         }
 
-        self.offset = self.entries.len();
+        self.reset_cursor();
 
         Ok(())
     }
 
+    // The cursor goes from 0, the oldest entry, to the number of entries, past the newest one
+    // where a fresh line is typed. Going back stops on the oldest entry, unless wrapping.
     pub fn prev_entry(&mut self) -> Option<String> {
         if self.entries.is_empty() {
             return None;
         }
 
-        if self.offset == 1 && self.entries.len() == 1 {
-            return self.entries.first().cloned();
-        }
-
-        if self.offset >= 1 {
-            self.offset -= 1;
-        }
+        self.offset = match self.offset {
+            0 if self.wrap => self.entries.len() - 1,
+            0 => 0,
+            offset => offset.min(self.entries.len()) - 1,
+        };
 
-        Some(
-            self.entries
-                .get(self.offset)
-                .cloned()
-                .expect("My maintainer miscalculated the history offset for prev_entry"),
-        )
+        self.entries
+            .get(self.offset)
+            .map(|entry| entry.line.clone())
     }
 
+    // Moves forward up to the newest entry. Moving past it returns `None`, leaving the cursor
+    // where a fresh line is typed, unless wrapping back to the oldest entry.
     pub fn next_entry(&mut self) -> Option<String> {
-        if self.entries.is_empty() || self.offset == self.entries.len() {
+        if self.entries.is_empty() {
             return None;
         }
 
-        self.offset += 1;
+        self.offset = match self.offset {
+            offset if offset >= self.entries.len() && self.wrap => 0,
+            offset if offset >= self.entries.len() => return None,
+            offset => offset + 1,
+        };
 
-        if self.offset == self.entries.len() {
-            return None;
+        self.entries
+            .get(self.offset)
+            .map(|entry| entry.line.clone())
+    }
+
+    fn evict(&mut self) {
+        if let Some(limit) = self.limit {
+            while self.entries.len() > limit {
+                self.entries.pop_front();
+            }
+        }
+    }
+
+    // The `n` most recent entries, newest first.
+    pub fn recent(&self, n: usize) -> impl Iterator<Item = &HistoryEntry> {
+        self.entries.iter().rev().take(n)
+    }
+
+    // Records how running the last entry went, persisting it along with the entry.
+    pub fn annotate_last(&mut self, meta: EntryMeta) -> io::Result<()> {
+        if let Some(entry) = self.entries.back_mut() {
+            entry.meta = Some(meta);
+            self.persistence.persist(self.entries.make_contiguous())?;
         }
 
-        Some(
-            self.entries
-                .get(self.offset)
-                .cloned()
-                .expect("My maintainer miscalculated the history offset for next_entry"),
-        )
+        Ok(())
+    }
+
+    // Called with every entry actually stored, once it got persisted. Entries skipped as
+    // duplicates don't trigger it.
+    pub fn on_push(&mut self, on_push: impl FnMut(&str) + Send + 'static) {
+        self.on_push = Some(Box::new(on_push));
+    }
+
+    // Whether navigating past either end of the history starts over from the other end.
+    pub fn set_wrap(&mut self, wrap: bool) {
+        self.wrap = wrap;
+    }
+
+    // Whether pushing a line drops its older occurrences, so every entry is unique.
+    pub fn set_erase_dups(&mut self, erase_dups: bool) {
+        self.erase_dups = erase_dups;
+    }
+
+    // Most recent entry older than the one at index `from` containing `query`, along with its
+    // index. Pass the number of entries to search them all.
+    pub fn search_backward(&self, query: &str, from: usize) -> Option<(usize, String)> {
+        self.entries
+            .iter()
+            .enumerate()
+            .take(from)
+            .rev()
+            .find(|(_, entry)| entry.line.contains(query))
+            .map(|(idx, entry)| (idx, entry.line.clone()))
+    }
+
+    pub fn cursor(&self) -> usize {
+        self.offset
     }
 
-    pub fn entries(&self) -> &Vec<String> {
+    // Moves the cursor past the newest entry, where a fresh line is typed.
+    pub fn reset_cursor(&mut self) {
+        self.offset = self.entries.len();
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    pub fn entries(&self) -> &VecDeque<HistoryEntry> {
         &self.entries
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn history(lines: &[&str]) -> History<Noop> {
+        let mut history = in_memory_history(None).unwrap();
+
+        for line in lines {
+            history.push(line.to_string()).unwrap();
+        }
+
+        history
+    }
+
+    #[test]
+    fn navigation_stops_at_both_ends_by_default() {
+        let mut history = history(&["a", "b"]);
+
+        assert_eq!(history.prev_entry().as_deref(), Some("b"));
+        assert_eq!(history.prev_entry().as_deref(), Some("a"));
+        assert_eq!(history.prev_entry().as_deref(), Some("a"));
+
+        assert_eq!(history.next_entry().as_deref(), Some("b"));
+        assert_eq!(history.next_entry(), None);
+        assert_eq!(history.next_entry(), None);
+        assert_eq!(history.prev_entry().as_deref(), Some("b"));
+    }
+
+    #[test]
+    fn navigation_wraps_around_both_ends() {
+        let mut history = history(&["a", "b"]);
+        history.set_wrap(true);
+
+        assert_eq!(history.prev_entry().as_deref(), Some("b"));
+        assert_eq!(history.prev_entry().as_deref(), Some("a"));
+        assert_eq!(history.prev_entry().as_deref(), Some("b"));
+
+        assert_eq!(history.next_entry(), None);
+        assert_eq!(history.next_entry().as_deref(), Some("a"));
+    }
+
+    #[test]
+    fn searching_backward_starts_below_the_given_index() {
+        let history = history(&["git status", "ls", "git commit", "cd"]);
+
+        assert_eq!(
+            history.search_backward("git", history.len()),
+            Some((2, "git commit".to_string()))
+        );
+        assert_eq!(
+            history.search_backward("git", 2),
+            Some((0, "git status".to_string()))
+        );
+        assert_eq!(history.search_backward("git", 0), None);
+        assert_eq!(history.search_backward("svn", history.len()), None);
+        assert_eq!(
+            history.search_backward("", history.len()),
+            Some((3, "cd".to_string()))
+        );
+    }
+
+    // Keeps the lines as last persisted.
+    #[derive(Default)]
+    struct Stored(Vec<String>);
+
+    impl Persistence for Stored {
+        fn load(&mut self) -> io::Result<Vec<HistoryEntry>> {
+            Ok(self.0.iter().map(HistoryEntry::new).collect())
+        }
+
+        fn persist(&mut self, history: &[HistoryEntry]) -> io::Result<()> {
+            self.0 = history.iter().map(|entry| entry.line.clone()).collect();
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn pushing_past_the_limit_drops_the_oldest_entries() {
+        let mut history = History::new(Stored::default(), Some(100)).unwrap();
+
+        for n in 0..1_000 {
+            history.push(n.to_string()).unwrap();
+        }
+
+        assert_eq!(history.entries().len(), 100);
+        assert_eq!(history.entries()[0].line, "900");
+        assert_eq!(history.entries()[99].line, "999");
+        assert_eq!(history.persistence.0.len(), 100);
+        assert_eq!(history.persistence.0[99], "999");
+    }
+
+    #[test]
+    fn loading_past_the_limit_truncates_the_persisted_entries() {
+        let stored = Stored((0..10).map(|n| n.to_string()).collect());
+        let history = History::new(stored, Some(3)).unwrap();
+
+        assert_eq!(history.persistence.0, ["7", "8", "9"]);
+        assert_eq!(history.cursor(), 3);
+    }
+
+    fn lines<A: Persistence>(history: &History<A>) -> Vec<&str> {
+        history
+            .entries()
+            .iter()
+            .map(|entry| entry.line.as_str())
+            .collect()
+    }
+
+    #[test]
+    fn repeating_the_last_line_is_skipped() {
+        assert_eq!(lines(&history(&["a", "b", "b", "a"])), ["a", "b", "a"]);
+    }
+
+    #[test]
+    fn erasing_dups_keeps_the_latest_occurrence_only() {
+        let mut history = history(&[]);
+        history.set_erase_dups(true);
+
+        for line in ["a", "b", "c", "a", "b"] {
+            history.push(line.to_string()).unwrap();
+        }
+
+        assert_eq!(lines(&history), ["c", "a", "b"]);
+    }
+
+    #[test]
+    fn up_up_down_down_comes_back_to_a_fresh_line() {
+        let mut history = history(&["a", "b", "c"]);
+
+        assert_eq!(history.prev_entry().as_deref(), Some("c"));
+        assert_eq!(history.prev_entry().as_deref(), Some("b"));
+        assert_eq!(history.next_entry().as_deref(), Some("c"));
+        assert_eq!(history.next_entry(), None);
+    }
+
+    #[test]
+    fn down_from_the_oldest_entry_visits_every_entry() {
+        let mut history = history(&["a", "b", "c"]);
+
+        for _ in 0..3 {
+            history.prev_entry();
+        }
+
+        assert_eq!(history.next_entry().as_deref(), Some("b"));
+        assert_eq!(history.next_entry().as_deref(), Some("c"));
+        assert_eq!(history.next_entry(), None);
+        assert_eq!(history.next_entry(), None);
+        assert_eq!(history.prev_entry().as_deref(), Some("c"));
+    }
+
+    #[test]
+    fn the_cursor_follows_navigation_until_reset() {
+        let mut history = history(&["a", "b", "c"]);
+
+        assert_eq!(history.len(), 3);
+        assert_eq!(history.cursor(), 3);
+
+        history.prev_entry();
+        history.prev_entry();
+        assert_eq!(history.cursor(), 1);
+
+        history.next_entry();
+        assert_eq!(history.cursor(), 2);
+
+        history.reset_cursor();
+        assert_eq!(history.cursor(), 3);
+        assert_eq!(history.prev_entry().as_deref(), Some("c"));
+
+        history.push("d".to_string()).unwrap();
+        assert_eq!(history.len(), 4);
+        assert_eq!(history.cursor(), 4);
+    }
+
+    #[test]
+    fn an_empty_history_has_nothing_to_navigate() {
+        let mut history = history(&[]);
+
+        assert!(history.is_empty());
+        assert_eq!(history.prev_entry(), None);
+        assert_eq!(history.next_entry(), None);
+        assert_eq!(history.cursor(), 0);
+    }
+}
@@ -1,18 +1,45 @@
+#[cfg(feature = "serde")]
+pub mod config;
+pub mod keymap;
+mod kill_ring;
 pub mod params;
+mod search;
+pub mod split;
+mod vi;
 
-use crate::history::{file_backed_history, in_memory_history, History};
-use crate::persistence::{FileBackend, Noop, Persistence};
+use self::keymap::{Action, KeyCombo, Keymap};
+use self::kill_ring::KillRing;
+use self::search::Search;
+use self::split::Splitter;
+use self::vi::{ViKey, ViMode, ViState};
+use crate::completion::{columns, common_prefix, Completer, Completion, CompletionStyle};
+use crate::history::{file_backed_history, in_memory_history, EntryMeta, History, HistoryEntry};
+use crate::persistence::{FileBackend, HistoryErrorPolicy, Noop, Persistence};
+use crate::terminal::{
+    Channel, EventSource, Events, Output, QueryEvents, RawMode, TerminalEvents, TerminalGuard,
+};
+use crate::width::{caret_notation, char_width, display_width, strip_escapes};
 use crate::Params;
-use clap::Parser;
+use clap::{ArgMatches, Parser};
 use crossterm::event::{Event, KeyCode, KeyEvent, KeyModifiers};
 use crossterm::{
     cursor::{self, MoveTo, MoveToNextLine},
-    event, queue,
-    terminal::{disable_raw_mode, enable_raw_mode, Clear, ClearType},
+    queue,
+    style::{Attribute, SetAttribute},
+    terminal::{self, Clear, ClearType, EnterAlternateScreen, LeaveAlternateScreen},
 };
-use std::fmt::Display;
+use std::any::Any;
+use std::collections::{HashMap, VecDeque};
+use std::fmt::{self, Display};
 use std::io::{self, Write};
-use std::path::Path;
+use std::ops::Range;
+use std::path::{Path, PathBuf};
+use std::process::{self, Command};
+use std::str::FromStr;
+use std::sync::mpsc::{self, Receiver, Sender};
+use std::sync::Arc;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+use std::{env, fs};
 
 #[derive(Debug, Clone)]
 pub struct Options {
@@ -23,6 +50,52 @@ pub struct Options {
     date: String,
     command_prompt: Option<String>,
     disable_free_expression: bool,
+    compact_prompt: bool,
+    prompt_suffix: String,
+    on_history_error: HistoryErrorPolicy,
+    redraw_strategy: RedrawStrategy,
+    strict_command_prefix: bool,
+    continuation_prompt: String,
+    is_complete: Option<Hook<CompletenessCheck>>,
+    line_validator: Option<Hook<LineValidator>>,
+    search_prompt: String,
+    return_parse_errors: bool,
+    completer: Option<Hook<dyn Completer + Send + Sync>>,
+    completion_style: CompletionStyle,
+    paginate_completions: bool,
+    match_brackets: bool,
+    interrupt_key: (KeyCode, KeyModifiers),
+    history_wrap: bool,
+    sanitize_input: bool,
+    live_prompt: Option<(Duration, Hook<PromptFn>)>,
+    esc_action: EscAction,
+    manage_raw_mode: bool,
+    alternate_screen: bool,
+    history_limit: Option<usize>,
+    multiline_gutter: bool,
+    ui_stream: UiStream,
+    double_ctrl_c_exits: bool,
+    mask_spans: Option<Hook<MaskSpans>>,
+    assume_position: Option<(u16, u16)>,
+    keymap: Keymap,
+    clap_program_name: Option<String>,
+    caret_notation: bool,
+    transient_prompt: Option<String>,
+    position_query: PositionQuery,
+    tab_behavior: Option<TabBehavior>,
+    recall_marker: String,
+    search_mode: SearchMode,
+    arg_splitter: Splitter,
+    script_comments: Option<String>,
+    exit_after_script: bool,
+    ignore_space: bool,
+    ignore_dups: bool,
+    vi_mode: bool,
+    vi_mode_indicators: Option<(String, String)>,
+    keep_cursor_column: bool,
+    exit_keywords: Vec<String>,
+    tips: Vec<String>,
+    random_tips: bool,
 }
 
 impl Default for Options {
@@ -35,6 +108,52 @@ impl Default for Options {
             date: Default::default(),
             command_prompt: None,
             disable_free_expression: false,
+            compact_prompt: false,
+            prompt_suffix: " ".to_string(),
+            on_history_error: HistoryErrorPolicy::default(),
+            redraw_strategy: RedrawStrategy::default(),
+            strict_command_prefix: false,
+            continuation_prompt: "..".to_string(),
+            is_complete: None,
+            line_validator: None,
+            search_prompt: "(reverse-i-search)".to_string(),
+            return_parse_errors: false,
+            completer: None,
+            completion_style: CompletionStyle::default(),
+            paginate_completions: false,
+            match_brackets: false,
+            interrupt_key: (KeyCode::Char('c'), KeyModifiers::CONTROL),
+            history_wrap: false,
+            sanitize_input: false,
+            live_prompt: None,
+            esc_action: EscAction::default(),
+            manage_raw_mode: true,
+            alternate_screen: false,
+            history_limit: None,
+            multiline_gutter: false,
+            ui_stream: UiStream::default(),
+            double_ctrl_c_exits: false,
+            mask_spans: None,
+            assume_position: None,
+            keymap: Keymap::emacs_default(),
+            clap_program_name: None,
+            caret_notation: false,
+            transient_prompt: None,
+            position_query: PositionQuery::default(),
+            tab_behavior: None,
+            recall_marker: String::new(),
+            search_mode: SearchMode::default(),
+            arg_splitter: Splitter::default(),
+            script_comments: None,
+            exit_after_script: false,
+            ignore_space: false,
+            ignore_dups: false,
+            vi_mode: false,
+            vi_mode_indicators: None,
+            keep_cursor_column: false,
+            exit_keywords: Vec::new(),
+            tips: Vec::new(),
+            random_tips: false,
         }
     }
 }
@@ -82,365 +201,4195 @@ impl Options {
         }
     }
 
-    pub fn command_prompt(self, prompt: impl AsRef<str>) -> Self {
+    pub fn compact_prompt(self) -> Self {
         Self {
-            command_prompt: Some(prompt.as_ref().to_string()),
+            compact_prompt: true,
             ..self
         }
     }
-}
-
-#[derive(Debug)]
-pub enum Input<C> {
-    String(String),
-    Exit,
-    Command(C),
-}
 
-impl<A> Input<A> {
-    pub fn map<F, B>(self, fun: F) -> Input<B>
-    where
-        F: FnOnce(A) -> B,
-    {
-        match self {
-            Input::String(s) => Input::String(s),
-            Input::Exit => Input::Exit,
-            Input::Command(a) => Input::Command(fun(a)),
+    pub fn prompt_suffix(self, suffix: impl AsRef<str>) -> Self {
+        Self {
+            prompt_suffix: suffix.as_ref().to_string(),
+            ..self
         }
     }
 
-    pub fn flat_map<F, B>(self, fun: F) -> Input<B>
-    where
-        F: FnOnce(A) -> Input<B>,
-    {
-        match self {
-            Input::String(s) => Input::String(s),
-            Input::Exit => Input::Exit,
-            Input::Command(a) => fun(a),
+    pub fn on_history_error(self, policy: HistoryErrorPolicy) -> Self {
+        Self {
+            on_history_error: policy,
+            ..self
         }
     }
-}
-
-#[derive(Default)]
-pub struct PromptOptions {
-    prompt: Option<String>,
-}
 
-impl PromptOptions {
-    pub fn prompt(self, prompt: impl AsRef<str>) -> Self {
+    pub fn redraw_strategy(self, strategy: RedrawStrategy) -> Self {
         Self {
-            prompt: Some(prompt.as_ref().to_string()),
+            redraw_strategy: strategy,
+            ..self
         }
     }
-}
 
-pub struct Inputs<A> {
-    options: Options,
-    terminated: bool,
-    buffer: String,
-    offset: u16,
-    history: History<A>,
-    inflight_buffer: Option<String>,
-}
+    /// By default, whitespace before the command prefix is ignored so `  :connect` is still a
+    /// command. In strict mode, the prefix must be the first character typed, which leaves
+    /// space-prefixed lines to free expressions.
+    pub fn strict_command_prefix(self) -> Self {
+        Self {
+            strict_command_prefix: true,
+            ..self
+        }
+    }
 
-pub fn in_memory_inputs(options: Options) -> io::Result<Inputs<Noop>> {
-    Inputs::new(options, in_memory_history()?)
-}
+    /// Keeps accumulating lines, each one drawn after `prompt`, until `is_complete` accepts the
+    /// whole text. Only then is the assembled input handed to the parser. The interrupt key
+    /// abandons a statement spanning several lines, leaving it on screen above a fresh prompt.
+    pub fn continuation(
+        self,
+        prompt: impl AsRef<str>,
+        is_complete: impl Fn(&str) -> bool + Send + Sync + 'static,
+    ) -> Self {
+        Self {
+            continuation_prompt: prompt.as_ref().to_string(),
+            is_complete: Some(Hook(Arc::new(is_complete))),
+            ..self
+        }
+    }
 
-pub fn file_backed_inputs(
-    options: Options,
-    path: impl AsRef<Path>,
-) -> io::Result<Inputs<FileBackend>> {
-    Inputs::new(options, file_backed_history(path)?)
-}
+    /// Checks every physical line when Enter is pressed. A rejected line is reported below the
+    /// input and stays editable.
+    pub fn line_validator(
+        self,
+        validate: impl Fn(&str) -> Result<(), String> + Send + Sync + 'static,
+    ) -> Self {
+        Self {
+            line_validator: Some(Hook(Arc::new(validate))),
+            ..self
+        }
+    }
 
-impl<A> Inputs<A>
-where
-    A: Persistence,
-{
-    pub fn new(options: Options, history: History<A>) -> io::Result<Inputs<A>> {
-        let mut padding = false;
+    pub fn search_prompt(self, prompt: impl AsRef<str>) -> Self {
+        Self {
+            search_prompt: prompt.as_ref().to_string(),
+            ..self
+        }
+    }
 
-        if !options.header.is_empty() {
-            println!("{}", options.header);
-            padding = true;
+    /// Returns parser failures as `Input::Error` instead of printing them and prompting again.
+    pub fn return_parse_errors(self) -> Self {
+        Self {
+            return_parse_errors: true,
+            ..self
         }
+    }
 
-        if !options.author.is_empty() {
-            println!("Author: {}", options.author);
-            padding = true;
+    pub fn completer(self, completer: impl Completer + Send + Sync + 'static) -> Self {
+        Self {
+            completer: Some(Hook(Arc::new(completer))),
+            ..self
         }
+    }
 
-        if !options.version.is_empty() {
-            println!("Version: {}", options.version);
-            padding = true;
+    pub fn completion_style(self, style: CompletionStyle) -> Self {
+        Self {
+            completion_style: style,
+            ..self
         }
+    }
 
-        if !options.date.is_empty() {
-            println!("Date: {}", options.date);
-            padding = true;
+    /// A completion menu taller than the terminal stops after every screenful on a `--More--`
+    /// prompt. Space shows the next screenful, Enter one more row, and any other key ends the listing.
+    pub fn paginate_completions(self) -> Self {
+        Self {
+            paginate_completions: true,
+            ..self
         }
+    }
 
-        if padding {
-            println!();
+    /// Underlines the bracket under or right before the cursor along with the one it pairs with.
+    pub fn match_brackets(self) -> Self {
+        Self {
+            match_brackets: true,
+            ..self
         }
+    }
 
-        Ok(Inputs {
-            options,
-            terminated: false,
-            buffer: String::new(),
-            offset: 0,
-            history,
-            inflight_buffer: None,
-        })
+    /// Key ending the session, Ctrl-C by default. It takes precedence over any other binding.
+    pub fn interrupt_key(self, code: KeyCode, modifiers: KeyModifiers) -> Self {
+        Self {
+            interrupt_key: (code, modifiers),
+            ..self
+        }
     }
 
-    pub fn next_input(&mut self) -> io::Result<Option<Input<Params>>> {
-        self.next_input_with_options(&Default::default())
+    /// When enabled, going up past the oldest history entry wraps around to the newest one, and
+    /// going down past the line being typed wraps around to the oldest one.
+    pub fn history_wrap(self, wrap: bool) -> Self {
+        Self {
+            history_wrap: wrap,
+            ..self
+        }
     }
 
-    pub fn next_input_with_options(
-        &mut self,
-        options: &PromptOptions,
-    ) -> io::Result<Option<Input<Params>>> {
-        self.next_input_with(options, |args| Ok::<_, String>(Params::new(args)))
+    /// Leaves escape sequences and other control characters out when drawing the buffer, so a
+    /// pasted line or an entry from an untrusted history file can't move the cursor or change
+    /// colors. Only the display is affected: the submitted line and the history keep them as is.
+    pub fn sanitize_input(self) -> Self {
+        Self {
+            sanitize_input: true,
+            ..self
+        }
     }
 
-    pub fn next_input_with_parser<P: Parser>(&mut self) -> io::Result<Option<Input<P>>> {
-        self.next_input_with_parser_and_options::<P>(&Default::default())
+    /// Computes the prompt with `prompt`, and computes it again every `interval` while waiting for
+    /// a key. The prompt gets drawn again whenever it changes, leaving the line being typed and
+    /// the cursor where they are. Useful for a clock or a status shown in the prompt.
+    pub fn live_prompt(
+        self,
+        interval: Duration,
+        prompt: impl Fn() -> String + Send + Sync + 'static,
+    ) -> Self {
+        Self {
+            live_prompt: Some((interval, Hook(Arc::new(prompt)))),
+            ..self
+        }
     }
 
-    pub fn next_input_with_parser_and_options<P: Parser>(
-        &mut self,
-        options: &PromptOptions,
-    ) -> io::Result<Option<Input<P>>> {
-        let cmd_prompt = if let Some(prompt) = self.options.command_prompt.clone() {
-            prompt
-        } else {
-            " ".to_string()
-        };
+    pub fn esc_action(self, action: EscAction) -> Self {
+        Self {
+            esc_action: action,
+            ..self
+        }
+    }
 
-        self.next_input_with(options, move |args| {
-            let mut updated = vec![cmd_prompt.clone()];
-            updated.extend(args);
-            P::try_parse_from(updated)
-        })
+    /// Whether glyph enables and disables raw mode by itself, which it does by default. Pass
+    /// `false` when embedding glyph in an application owning the terminal. The caller then has to
+    /// keep raw mode enabled whenever glyph reads keys, that is while `next_input` and the likes,
+    /// `read_key`, `confirm` and `select` run, and `with_cooked_mode` leaves raw mode as it is.
+    /// Newlines glyph prints are written as carriage return and newline, as raw mode requires.
+    pub fn manage_raw_mode(self, manage: bool) -> Self {
+        Self {
+            manage_raw_mode: manage,
+            ..self
+        }
     }
 
-    pub fn next_input_with<F, E, C>(
-        &mut self,
-        options: &PromptOptions,
-        parser: F,
-    ) -> io::Result<Option<Input<C>>>
-    where
-        E: Display,
-        F: Fn(Vec<String>) -> Result<C, E>,
-    {
-        if self.terminated {
-            return Ok(None);
+    /// Runs the session in the terminal alternate screen, leaving the original content untouched.
+    /// The original screen is back once the session ends, or when `Inputs` is dropped.
+    pub fn alternate_screen(self) -> Self {
+        Self {
+            alternate_screen: true,
+            ..self
         }
+    }
 
-        enable_raw_mode()?;
-        let mut stdout = io::stdout();
+    /// Keeps at most `limit` history entries, the oldest ones being dropped first. Entries are held
+    /// in a ring, so staying under the limit costs nothing whatever the backend. A history file
+    /// holding more entries gets truncated when loaded.
+    pub fn history_limit(self, limit: usize) -> Self {
+        Self {
+            history_limit: Some(limit),
+            ..self
+        }
+    }
 
-        let (_, y) = cursor::position()?;
+    /// Starts every row of the input with its line number, which helps finding one's way in
+    /// longer multiline snippets.
+    pub fn multiline_gutter(self, enabled: bool) -> Self {
+        Self {
+            multiline_gutter: enabled,
+            ..self
+        }
+    }
 
-        queue!(stdout, MoveTo(0, y + 1))?;
-        let prompt = if let Some(prefix) = options.prompt.as_ref() {
-            format!("{} {}", prefix, self.options.prompt)
-        } else {
-            self.options.prompt.clone()
-        };
+    /// Stream the header, the prompt and the line being edited are written to.
+    pub fn ui_stream(self, stream: UiStream) -> Self {
+        Self {
+            ui_stream: stream,
+            ..self
+        }
+    }
 
-        let start_pos = prompt.chars().count() as u16 + 2;
-        write!(stdout, "{} ", prompt)?;
+    /// The interrupt key cancels the line being typed, and only exits when pressed again right
+    /// after. Any other key pressed in between, Alt-digit counts included, starts over. Other
+    /// events, like resizing the terminal, leave it armed.
+    pub fn double_ctrl_c_exits(self) -> Self {
+        Self {
+            double_ctrl_c_exits: true,
+            ..self
+        }
+    }
 
-        stdout.flush()?;
+    /// Hides sensitive parts of the line, like a token passed to a command. `spans` gets the line
+    /// and returns the byte ranges to hide. They're drawn as `*` while typing, and every one of
+    /// them is stored as `***` in the history. The input handed back keeps them as typed.
+    pub fn mask_spans(
+        self,
+        spans: impl Fn(&str) -> Vec<Range<usize>> + Send + Sync + 'static,
+    ) -> Self {
+        Self {
+            mask_spans: Some(Hook(Arc::new(spans))),
+            ..self
+        }
+    }
 
-        loop {
-            let c = event::read()?;
-            let (_, y) = cursor::position()?;
+    /// Never asks the terminal where the cursor is, for terminals not answering or answering too
+    /// slowly. The first prompt starts from `(column, row)`, the next ones right below the
+    /// previous input. Whatever gets printed in between isn't accounted for.
+    pub fn assume_position(self, column: u16, row: u16) -> Self {
+        Self {
+            assume_position: Some((column, row)),
+            ..self
+        }
+    }
 
-            if let Event::Key(KeyEvent { code, modifiers }) = c {
-                match code {
-                    KeyCode::Char('a') if modifiers.contains(KeyModifiers::CONTROL) => {
-                        self.offset = 0;
-                        queue!(stdout, MoveTo(2, y))?;
-                    }
+    /// What keys do, `Keymap::emacs_default` unless told otherwise.
+    pub fn keymap(self, keymap: Keymap) -> Self {
+        Self { keymap, ..self }
+    }
 
-                    KeyCode::Char('e') if modifiers.contains(KeyModifiers::CONTROL) => {
-                        self.offset = self.buffer.len() as u16;
-                        queue!(stdout, MoveTo(2 + self.offset, y))?;
-                    }
+    /// Program name clap parsers see, showing in their usage and error messages. Defaults to the
+    /// name of the running binary. The command prefix is only used to tell commands apart.
+    pub fn clap_program_name(self, name: impl AsRef<str>) -> Self {
+        Self {
+            clap_program_name: Some(name.as_ref().to_string()),
+            ..self
+        }
+    }
 
-                    KeyCode::Char('c') if modifiers.contains(KeyModifiers::CONTROL) => {
-                        queue!(stdout, MoveTo(0, y))?;
-                        println!();
-                        self.terminated = true;
-                        disable_raw_mode()?;
-                        return Ok(Some(Input::Exit));
-                    }
+    /// Shows control characters in the line the way terminals echo them, `^C` or `^[` for
+    /// instance, instead of writing them as is. The line itself keeps the actual characters.
+    pub fn caret_notation(self) -> Self {
+        Self {
+            caret_notation: true,
+            ..self
+        }
+    }
 
-                    KeyCode::Backspace if self.offset > 0 => {
-                        self.offset -= 1;
-                        self.buffer.remove(self.offset as usize);
-                        queue!(stdout, MoveTo(0, y), Clear(ClearType::CurrentLine))?;
-                        write!(stdout, "{} {}", prompt, self.buffer)?;
-                        queue!(stdout, MoveTo(start_pos + self.offset - 1, y))?;
+    /// Prompt the submitted lines are drawn again with, usually shorter than the regular one to
+    /// keep the scrollback tidy. It's used as is, without the prompt suffix.
+    pub fn transient_prompt(self, prompt: impl AsRef<str>) -> Self {
+        Self {
+            transient_prompt: Some(prompt.as_ref().to_string()),
+            ..self
+        }
+    }
 
-                        if self.buffer.is_empty() {
-                            self.inflight_buffer = None;
-                        } else {
-                            self.inflight_buffer = Some(self.buffer.clone());
-                        }
-                    }
+    /// Terminal glyph asks where the cursor is, which matters when stdin, stdout and stderr don't
+    /// all belong to the same one. Raw mode is always set on stdin, or on the controlling
+    /// terminal when stdin isn't one.
+    pub fn position_query(self, query: PositionQuery) -> Self {
+        Self {
+            position_query: query,
+            ..self
+        }
+    }
 
-                    KeyCode::Left if self.offset > 0 => {
-                        self.offset -= 1;
-                        queue!(stdout, MoveTo(0, y), Clear(ClearType::CurrentLine))?;
-                        write!(stdout, "{} {}", prompt, self.buffer)?;
-                        queue!(stdout, MoveTo(start_pos + self.offset - 1, y))?;
-                    }
+    /// What Tab does. By default it completes when there's a completer, and does nothing
+    /// otherwise. Asking for `TabBehavior::Complete` without a completer fails when building
+    /// `Inputs`.
+    pub fn tab_behavior(self, behavior: TabBehavior) -> Self {
+        Self {
+            tab_behavior: Some(behavior),
+            ..self
+        }
+    }
 
-                    KeyCode::Right if self.offset < self.buffer.len() as u16 => {
-                        self.offset += 1;
-                        queue!(stdout, MoveTo(0, y), Clear(ClearType::CurrentLine))?;
-                        write!(stdout, "{} {}", prompt, self.buffer)?;
-                        queue!(stdout, MoveTo(start_pos + self.offset - 1, y))?;
-                    }
+    /// Drawn between the prompt and the line while it shows a history entry, telling recalled
+    /// lines from typed ones.
+    pub fn recall_marker(self, marker: impl AsRef<str>) -> Self {
+        Self {
+            recall_marker: marker.as_ref().to_string(),
+            ..self
+        }
+    }
 
-                    KeyCode::Up => {
-                        if let Some(entry) = self.history.prev_entry() {
-                            self.offset = entry.len() as u16;
-                            self.buffer = entry;
+    /// How Ctrl-R matches history entries and completion candidates are filtered against the
+    /// token under the cursor.
+    pub fn search_mode(self, mode: SearchMode) -> Self {
+        Self {
+            search_mode: mode,
+            ..self
+        }
+    }
 
-                            queue!(stdout, MoveTo(0, y), Clear(ClearType::CurrentLine))?;
-                            write!(stdout, "{} {}", prompt, self.buffer)?;
-                            queue!(stdout, MoveTo(start_pos + self.offset - 1, y))?;
-                        }
-                    }
+    /// How commands are cut into arguments before reaching the parser, clap's included.
+    pub fn arg_splitter(self, splitter: Splitter) -> Self {
+        Self {
+            arg_splitter: splitter,
+            ..self
+        }
+    }
 
-                    KeyCode::Down => {
-                        if let Some(entry) = self
-                            .history
-                            .next_entry()
-                            .or_else(|| self.inflight_buffer.clone())
-                            .or_else(|| Some("".to_string()))
-                        {
-                            self.offset = entry.len() as u16;
-                            self.buffer = entry;
+    /// Drops queued script lines starting with `prefix`, leading whitespace aside, along with
+    /// blank ones, instead of echoing them after the prompt.
+    pub fn script_comments(self, prefix: impl AsRef<str>) -> Self {
+        Self {
+            script_comments: Some(prefix.as_ref().to_string()),
+            ..self
+        }
+    }
 
-                            queue!(stdout, MoveTo(0, y), Clear(ClearType::CurrentLine))?;
-                            write!(stdout, "{} {}", prompt, self.buffer)?;
-                            queue!(stdout, MoveTo(start_pos + self.offset - 1, y))?;
-                        }
-                    }
+    /// Ends the session with `Input::Exit` once the queued script lines ran out, instead of going
+    /// on interactively.
+    pub fn exit_after_script(self) -> Self {
+        Self {
+            exit_after_script: true,
+            ..self
+        }
+    }
 
-                    KeyCode::Enter => {
-                        let line = std::mem::take(&mut self.buffer);
-                        let line = line.as_str().trim();
+    /// Keeps lines starting with a space out of the history, like bash's `ignorespace`.
+    pub fn ignore_space(self) -> Self {
+        Self {
+            ignore_space: true,
+            ..self
+        }
+    }
 
-                        if line.is_empty() {
-                            writeln!(stdout)?;
-                            queue!(stdout, MoveToNextLine(1))?;
-                            write!(stdout, "{} ", prompt)?;
+    /// Drops the older occurrences of a line from the history when it gets stored again, instead
+    /// of only skipping a line repeating the one right before.
+    pub fn ignore_dups(self) -> Self {
+        Self {
+            ignore_dups: true,
+            ..self
+        }
+    }
+
+    /// Edits lines the vi way. Every prompt starts in insert mode, and Escape switches to normal
+    /// mode, where `h`, `l`, `w`, `b`, `0` and `$` move, `x` and `dd` delete, `k` and `j` go
+    /// through the history, and `i`, `a`, `I` and `A` get back to inserting.
+    pub fn vi_mode(self) -> Self {
+        Self {
+            vi_mode: true,
+            ..self
+        }
+    }
+
+    /// In vi mode, shows `insert` or `normal` in front of the prompt, depending on the mode.
+    pub fn vi_mode_indicators(self, insert: impl AsRef<str>, normal: impl AsRef<str>) -> Self {
+        Self {
+            vi_mode_indicators: Some((insert.as_ref().to_string(), normal.as_ref().to_string())),
+            ..self
+        }
+    }
+
+    /// Recalled history entries put the cursor on the column it was on, instead of at the end.
+    pub fn keep_cursor_column(self) -> Self {
+        Self {
+            keep_cursor_column: true,
+            ..self
+        }
+    }
+
+    /// Free expressions matching one of `keywords` end the session like Ctrl-C does. Commands are
+    /// always handed to the parser first, so a `:quit` command keeps working as usual.
+    pub fn exit_keywords(self, keywords: impl IntoIterator<Item = impl AsRef<str>>) -> Self {
+        Self {
+            exit_keywords: keywords
+                .into_iter()
+                .map(|k| k.as_ref().to_string())
+                .collect(),
+            ..self
+        }
+    }
+
+    /// Prints one of `tips` after the header. The tip changes every day unless `random_tips` is
+    /// set.
+    pub fn tips(self, tips: Vec<String>) -> Self {
+        Self { tips, ..self }
+    }
+
+    pub fn random_tips(self) -> Self {
+        Self {
+            random_tips: true,
+            ..self
+        }
+    }
+
+    pub fn command_prompt(self, prompt: impl AsRef<str>) -> Self {
+        Self {
+            command_prompt: Some(prompt.as_ref().to_string()),
+            ..self
+        }
+    }
+}
+
+// Shares user-provided callbacks so `Options` stays cheap to clone and printable.
+struct Hook<F: ?Sized>(Arc<F>);
+
+impl<F: ?Sized> Clone for Hook<F> {
+    fn clone(&self) -> Self {
+        Hook(self.0.clone())
+    }
+}
+
+impl<F: ?Sized> fmt::Debug for Hook<F> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("<hook>")
+    }
+}
+
+#[cfg(windows)]
+const DEFAULT_EDITOR: &str = "notepad";
+
+#[cfg(not(windows))]
+const DEFAULT_EDITOR: &str = "vi";
+
+const MAX_COUNT: usize = 9_999;
+
+const TAB_WIDTH: usize = 4;
+
+// How often messages sent through a `Printer` get printed while waiting for keys.
+const PRINTER_INTERVAL: Duration = Duration::from_millis(50);
+
+type CompletenessCheck = dyn Fn(&str) -> bool + Send + Sync;
+type LineValidator = dyn Fn(&str) -> Result<(), String> + Send + Sync;
+type PromptFn = dyn Fn() -> String + Send + Sync;
+type MaskSpans = dyn Fn(&str) -> Vec<Range<usize>> + Send + Sync;
+type ErrorRenderer = dyn Fn(&str) + Send + Sync;
+
+#[derive(Debug, Default, Clone, Copy, Eq, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(rename_all = "snake_case"))]
+pub enum RedrawStrategy {
+    /// Clears the whole line before writing it again.
+    #[default]
+    FullClear,
+    /// Writes over the previous content and only clears what's left past the end of the line,
+    /// which flickers less on slow terminals.
+    Overwrite,
+}
+
+#[derive(Debug, Default, Clone, Copy, Eq, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(rename_all = "snake_case"))]
+pub enum EscAction {
+    /// Esc does nothing.
+    #[default]
+    Ignore,
+    /// Empties the line being typed.
+    ClearLine,
+    /// Leaves the line being typed on screen and starts over with a fresh prompt below it.
+    Cancel,
+}
+
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(rename_all = "snake_case"))]
+pub enum TabBehavior {
+    /// Inserts spaces up to the next tab stop, every four columns.
+    Insert,
+    Ignore,
+    /// Completes the token under the cursor, Shift-Tab going through candidates backward.
+    Complete,
+}
+
+#[derive(Debug, Default, Clone, Copy, Eq, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(rename_all = "snake_case"))]
+pub enum PositionQuery {
+    /// Asked on the UI stream. The answer is read from stdin with a UI on stdout, from the
+    /// controlling terminal with a UI on stderr.
+    #[default]
+    Auto,
+    /// Asked and answered on the UI stream, for a UI drawn on another terminal than stdin's.
+    UiStream,
+    /// Asked and answered on the controlling terminal, whatever the standard streams are.
+    Tty,
+}
+
+#[derive(Debug, Default, Clone, Copy, Eq, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(rename_all = "snake_case"))]
+pub enum UiStream {
+    #[default]
+    Stdout,
+    /// Keeps stdout for the application output, so it can be piped without the prompt.
+    Stderr,
+}
+
+#[derive(Debug, Default, Clone, Copy, Eq, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(rename_all = "snake_case"))]
+pub enum SearchMode {
+    /// Matches the query as a substring, completion candidates being left as the completer
+    /// returned them.
+    #[default]
+    Exact,
+    /// Matches the query characters in order, not necessarily next to each other, `gco` matching
+    /// `git checkout origin`. Best matches come first.
+    #[cfg(feature = "fuzzy")]
+    Fuzzy,
+}
+
+#[derive(Debug)]
+pub enum Input<C> {
+    String(String),
+    Exit,
+    Command(C),
+    /// The submitted line was a command the parser rejected.
+    Error {
+        line: String,
+        error: String,
+    },
+    /// Nothing was submitted within `PromptOptions::timeout`.
+    Timeout,
+}
+
+impl<A> Input<A> {
+    pub fn map<F, B>(self, fun: F) -> Input<B>
+    where
+        F: FnOnce(A) -> B,
+    {
+        match self {
+            Input::String(s) => Input::String(s),
+            Input::Exit => Input::Exit,
+            Input::Command(a) => Input::Command(fun(a)),
+            Input::Error { line, error } => Input::Error { line, error },
+            Input::Timeout => Input::Timeout,
+        }
+    }
+
+    pub fn flat_map<F, B>(self, fun: F) -> Input<B>
+    where
+        F: FnOnce(A) -> Input<B>,
+    {
+        match self {
+            Input::String(s) => Input::String(s),
+            Input::Exit => Input::Exit,
+            Input::Command(a) => fun(a),
+            Input::Error { line, error } => Input::Error { line, error },
+            Input::Timeout => Input::Timeout,
+        }
+    }
+}
+
+/// What a parser made of a command, as returned to `Inputs::next_input_with_outcome`.
+#[derive(Debug)]
+pub enum ParseOutcome<C, E> {
+    /// A recognized command, handed back to the caller.
+    Complete(C),
+    /// The command goes on, the user keeps typing it on a continuation line.
+    Incomplete,
+    /// The command is wrong and reported as a parse error.
+    Error(E),
+}
+
+impl<C, E> From<Result<C, E>> for ParseOutcome<C, E> {
+    fn from(result: Result<C, E>) -> Self {
+        match result {
+            Ok(c) => ParseOutcome::Complete(c),
+            Err(e) => ParseOutcome::Error(e),
+        }
+    }
+}
+
+/// A submitted line along with how it was understood.
+#[derive(Debug)]
+pub struct Submission<C> {
+    pub input: Input<C>,
+    /// Whether the line was read as a command rather than a free expression.
+    pub was_command: bool,
+    /// The line as typed, before trimming or removing the command prefix. Empty when nothing was
+    /// typed, like when the session gets interrupted.
+    pub raw: String,
+}
+
+#[derive(Default)]
+pub struct PromptOptions {
+    prompt: Option<String>,
+    timeout: Option<Duration>,
+    no_history_nav: bool,
+}
+
+impl PromptOptions {
+    pub fn prompt(self, prompt: impl AsRef<str>) -> Self {
+        Self {
+            prompt: Some(prompt.as_ref().to_string()),
+            ..self
+        }
+    }
+
+    /// Gives up waiting for a line after `timeout`, returning `Input::Timeout`. The window starts
+    /// with the call, typing doesn't extend it. The partial line is kept, and shows up again with
+    /// the cursor where it was on the next call.
+    pub fn timeout(self, timeout: Duration) -> Self {
+        Self {
+            timeout: Some(timeout),
+            ..self
+        }
+    }
+
+    /// Up and Down leave the line alone instead of recalling history entries, for this prompt
+    /// only. Submitted lines still make it to the history.
+    pub fn no_history_nav(self) -> Self {
+        Self {
+            no_history_nav: true,
+            ..self
+        }
+    }
+}
+
+// Candidates being cycled through, along with the line as it was before the first one got applied.
+struct Cycle {
+    buffer: String,
+    offset: usize,
+    candidates: Vec<Completion>,
+    index: usize,
+}
+
+enum Interpreted<C, E> {
+    Skip,
+    Incomplete,
+    Invalid(E),
+    Accepted(Input<C>),
+}
+
+pub struct Inputs<A> {
+    options: Options,
+    terminated: bool,
+    buffer: String,
+    // Cursor position, in characters.
+    offset: usize,
+    history: History<A>,
+    inflight_buffer: Option<String>,
+    script: VecDeque<String>,
+    prompt_row: Option<u16>,
+    rendered_rows: u16,
+    search: Option<Search>,
+    highlight: Vec<Range<usize>>,
+    masked: Vec<Range<usize>>,
+    pending: Vec<Box<dyn Any + Send>>,
+    cycle: Option<Cycle>,
+    edits: HashMap<usize, (String, usize)>,
+    active_prompt: Option<String>,
+    ctrl_x: bool,
+    interrupted: bool,
+    last_arg: Option<(usize, Range<usize>)>,
+    kill_ring: KillRing,
+    killing: bool,
+    yanked: Option<Range<usize>>,
+    vi: ViState,
+    count: Option<usize>,
+    overwrite: bool,
+    events: Events,
+    out: Output,
+    raw_mode: RawMode,
+    alternate_screen: bool,
+    submitted: Option<(String, bool)>,
+    error_renderer: Option<Arc<ErrorRenderer>>,
+    printer: Option<(Sender<String>, Receiver<String>)>,
+}
+
+/// Prints messages above the prompt from any thread, see `Inputs::printer`.
+#[derive(Clone)]
+pub struct Printer {
+    sender: Sender<String>,
+}
+
+impl Printer {
+    /// Fails once the `Inputs` it comes from is gone.
+    pub fn print(&self, message: impl Display) -> io::Result<()> {
+        self.sender
+            .send(message.to_string())
+            .map_err(|_| io::Error::new(io::ErrorKind::BrokenPipe, "inputs are gone"))
+    }
+}
+
+pub fn in_memory_inputs(options: Options) -> io::Result<Inputs<Noop>> {
+    let history = in_memory_history(options.history_limit)?;
+    Inputs::new(options, history)
+}
+
+/// Replays the lines of the file at `path` as if they were typed, before going on interactively or,
+/// with `Options::exit_after_script`, exiting. The history is kept in memory.
+pub fn file_script_inputs(options: Options, path: impl AsRef<Path>) -> io::Result<Inputs<Noop>> {
+    let script = fs::read_to_string(path)?;
+    let mut inputs = in_memory_inputs(options)?;
+
+    inputs.queue_script(script.lines().map(|line| line.to_string()));
+
+    Ok(inputs)
+}
+
+pub fn file_backed_inputs(
+    options: Options,
+    path: impl AsRef<Path>,
+) -> io::Result<Inputs<FileBackend>> {
+    let history = file_backed_history(path, options.on_history_error, options.history_limit)?;
+    Inputs::new(options, history)
+}
+
+/// Like `file_backed_inputs`, storing the history as JSON lines that record when every entry got
+/// stored.
+#[cfg(feature = "jsonl")]
+pub fn jsonl_backed_inputs(
+    options: Options,
+    path: impl AsRef<Path>,
+) -> io::Result<Inputs<crate::persistence::JsonlBackend>> {
+    let history = crate::history::jsonl_backed_history(
+        path,
+        options.on_history_error,
+        options.history_limit,
+    )?;
+    Inputs::new(options, history)
+}
+
+impl<A> Inputs<A>
+where
+    A: Persistence,
+{
+    pub fn new(options: Options, history: History<A>) -> io::Result<Inputs<A>> {
+        let (ui, out) = match options.ui_stream {
+            UiStream::Stdout => (Channel::Stdout, Output::new(io::stdout())),
+            UiStream::Stderr => (Channel::Stderr, Output::new(io::stderr())),
+        };
+        let events = match (options.position_query, ui) {
+            (PositionQuery::Auto, Channel::Stdout) => Events::new(TerminalEvents),
+            (PositionQuery::Auto, ask) => Events::new(QueryEvents {
+                ask,
+                answer: Channel::Tty,
+            }),
+            (PositionQuery::UiStream, ui) => Events::new(QueryEvents {
+                ask: ui,
+                answer: ui,
+            }),
+            (PositionQuery::Tty, _) => Events::new(QueryEvents {
+                ask: Channel::Tty,
+                answer: Channel::Tty,
+            }),
+        };
+
+        Self::build(options, history, events, out)
+    }
+
+    /// Builds `Inputs` over `persistence`, reading its events from `events` and printing
+    /// everything to `writer` instead of the terminal. Raw mode is still switched on the real
+    /// terminal unless told otherwise, so use `Options::manage_raw_mode(false)` when running
+    /// without one, in tests for instance.
+    pub fn with_io(
+        options: Options,
+        persistence: A,
+        events: impl EventSource + Send + 'static,
+        writer: impl Write + Send + 'static,
+    ) -> io::Result<Inputs<A>> {
+        let history = History::new(persistence, options.history_limit)?;
+
+        Self::build(options, history, Events::new(events), Output::new(writer))
+    }
+
+    fn build(
+        options: Options,
+        mut history: History<A>,
+        events: Events,
+        out: Output,
+    ) -> io::Result<Inputs<A>> {
+        let mut out = if options.manage_raw_mode {
+            out
+        } else {
+            out.crlf()
+        };
+        let mut padding = false;
+
+        if options.tab_behavior == Some(TabBehavior::Complete) && options.completer.is_none() {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "completing on Tab requires a completer",
+            ));
+        }
+
+        history.set_wrap(options.history_wrap);
+        history.set_erase_dups(options.ignore_dups);
+
+        if options.alternate_screen {
+            queue!(out, EnterAlternateScreen, MoveTo(0, 0))?;
+        }
+
+        if !options.header.is_empty() {
+            writeln!(out, "{}", options.header)?;
+            padding = true;
+        }
+
+        if !options.author.is_empty() {
+            writeln!(out, "Author: {}", options.author)?;
+            padding = true;
+        }
+
+        if !options.version.is_empty() {
+            writeln!(out, "Version: {}", options.version)?;
+            padding = true;
+        }
+
+        if !options.date.is_empty() {
+            writeln!(out, "Date: {}", options.date)?;
+            padding = true;
+        }
+
+        if !options.tips.is_empty() {
+            let elapsed = SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .unwrap_or_default();
+            let seed = if options.random_tips {
+                elapsed.subsec_nanos() as u64
+            } else {
+                elapsed.as_secs() / 86_400
+            };
+
+            writeln!(
+                out,
+                "Tip: {}",
+                options.tips[seed as usize % options.tips.len()]
+            )?;
+            padding = true;
+        }
+
+        if padding {
+            writeln!(out)?;
+        }
+
+        out.flush()?;
+
+        Ok(Inputs {
+            terminated: false,
+            buffer: String::new(),
+            offset: 0,
+            history,
+            inflight_buffer: None,
+            script: VecDeque::new(),
+            prompt_row: None,
+            rendered_rows: 0,
+            search: None,
+            highlight: Vec::new(),
+            masked: Vec::new(),
+            pending: Vec::new(),
+            cycle: None,
+            edits: HashMap::new(),
+            active_prompt: None,
+            ctrl_x: false,
+            interrupted: false,
+            last_arg: None,
+            kill_ring: KillRing::default(),
+            killing: false,
+            yanked: None,
+            vi: ViState::default(),
+            count: None,
+            overwrite: false,
+            alternate_screen: options.alternate_screen,
+            submitted: None,
+            error_renderer: None,
+            printer: None,
+            options,
+            events,
+            out,
+            raw_mode: RawMode::default(),
+        })
+    }
+
+    pub fn queue_script(&mut self, lines: impl IntoIterator<Item = String>) {
+        self.script.extend(lines);
+    }
+
+    /// Terminal row the last prompt was drawn on, as requested by glyph before any scrolling
+    /// triggered by submitting the line. `None` until a prompt has been displayed.
+    pub fn prompt_row(&self) -> Option<u16> {
+        self.prompt_row
+    }
+
+    /// Prints `message` above the prompt, the input being drawn again below it as it was. When no
+    /// prompt is active, the message is simply printed.
+    pub fn print_above(&mut self, message: impl Display) -> io::Result<()> {
+        self.queue_above(message)?;
+        self.out.flush()
+    }
+
+    // `print_above`, leaving the output to be flushed.
+    fn queue_above(&mut self, message: impl Display) -> io::Result<()> {
+        let mut stdout = self.out.clone();
+
+        let (prompt, y) = match (self.active_prompt.clone(), self.prompt_row) {
+            (Some(prompt), Some(y)) => (prompt, y),
+            _ => return writeln!(stdout, "{}", message),
+        };
+
+        queue!(stdout, MoveTo(0, y), Clear(ClearType::FromCursorDown))?;
+
+        let width = self.terminal_width();
+        let mut rows = 0;
+
+        for line in message.to_string().lines() {
+            write!(stdout, "{}\r\n", line)?;
+            rows += display_width(line).div_ceil(width).max(1);
+        }
+
+        self.prompt_row = Some(y);
+        self.rendered_rows = 0;
+
+        let y = self.row_below(y, rows);
+        self.redraw(&mut stdout, &prompt, y)
+    }
+
+    /// Handle printing messages above the prompt, like `print_above`, but usable from any
+    /// thread. Messages are printed while glyph waits for keys, and otherwise on the next prompt.
+    pub fn printer(&mut self) -> Printer {
+        let (sender, _) = self.printer.get_or_insert_with(mpsc::channel);
+
+        Printer {
+            sender: sender.clone(),
+        }
+    }
+
+    /// Ends the session on the application's own terms: leaves the alternate screen when in use,
+    /// leaves raw mode unless the caller manages it and flushes whatever is left to print.
+    /// Dropping `Inputs` leaves the alternate screen as well but has to swallow errors, which
+    /// `finish` reports instead. History entries are persisted as they're submitted, so there's
+    /// nothing left to save for them.
+    pub fn finish(mut self) -> io::Result<()> {
+        self.leave_alternate_screen()?;
+        self.leave_raw_mode()?;
+        self.out.flush()
+    }
+
+    /// Hands parser errors to `render` instead of printing them as is. It runs with raw mode
+    /// disabled once the input got out of the way, and the prompt is drawn again below whatever
+    /// it printed.
+    pub fn set_error_renderer(&mut self, render: impl Fn(&str) + Send + Sync + 'static) {
+        self.error_renderer = Some(Arc::new(render));
+    }
+
+    /// Whether the terminal is in raw mode right now, whoever enabled it. Reports `false` when
+    /// that can't be told.
+    pub fn is_raw_mode_active(&self) -> bool {
+        terminal::is_raw_mode_enabled().unwrap_or(false)
+    }
+
+    pub fn options(&self) -> &Options {
+        &self.options
+    }
+
+    /// Changes the options between two prompts, with the builder methods on a copy for instance:
+    /// `*inputs.options_mut() = inputs.options().clone().prompt(">")`. Options applied once when
+    /// building `Inputs`, the UI stream, the alternate screen, raw mode management and the history
+    /// limit and wrapping, keep their initial effect.
+    pub fn options_mut(&mut self) -> &mut Options {
+        &mut self.options
+    }
+
+    pub fn set_prompt(&mut self, prompt: impl AsRef<str>) {
+        self.options.prompt = prompt.as_ref().to_string();
+    }
+
+    pub fn set_command_prompt(&mut self, prompt: impl AsRef<str>) {
+        self.options.command_prompt = Some(prompt.as_ref().to_string());
+    }
+
+    pub fn set_keymap(&mut self, keymap: Keymap) {
+        self.options.keymap = keymap;
+    }
+
+    /// Keys glyph reacts to, along with what they do, for help screens: the interrupt key unless
+    /// the keymap binds it, then the keymap bindings. History navigation is listed even though
+    /// `PromptOptions` can turn it off.
+    pub fn key_bindings(&self) -> Vec<(KeyCombo, String)> {
+        let keymap = &self.options.keymap;
+        let (code, modifiers) = self.options.interrupt_key;
+        let mut bindings = Vec::new();
+
+        if keymap.action(code, modifiers).is_none() {
+            let description = if self.options.double_ctrl_c_exits {
+                "cancel the line, exit when pressed twice".to_string()
+            } else {
+                Action::Interrupt.describe()
+            };
+
+            bindings.push((KeyCombo::new(code, modifiers), description));
+        }
+
+        bindings.extend(
+            keymap
+                .bindings()
+                .map(|(key, action)| (key, action.describe())),
+        );
+
+        bindings
+    }
+
+    pub fn set_free_expression(&mut self, enabled: bool) {
+        self.options.disable_free_expression = !enabled;
+    }
+
+    pub fn free_expression(&self) -> bool {
+        !self.options.disable_free_expression
+    }
+
+    /// Runs `fun` with raw mode disabled, so it can hand the terminal over to a pager, an editor
+    /// or anything expecting a regular terminal. When called while a prompt is active, the input
+    /// is moved out of the way first and drawn again below whatever `fun` printed.
+    pub fn with_cooked_mode<R>(&mut self, fun: impl FnOnce() -> R) -> io::Result<R> {
+        let mut stdout = self.out.clone();
+        let raw = terminal::is_raw_mode_enabled()?;
+
+        if let (Some(_), Some(y)) = (self.active_prompt.as_ref(), self.prompt_row) {
+            queue!(stdout, MoveTo(0, self.last_row(y)))?;
+            write!(stdout, "\r\n")?;
+        }
+
+        stdout.flush()?;
+
+        if raw {
+            self.leave_raw_mode()?;
+        }
+
+        let result = fun();
+
+        // What `fun` printed through glyph has to reach the terminal while still in cooked mode.
+        stdout.flush()?;
+
+        if raw {
+            self.enter_raw_mode()?;
+        }
+
+        if let Some(prompt) = self.active_prompt.clone() {
+            let (x, mut y) = self.position();
+
+            if x != 0 {
+                write!(stdout, "\r\n")?;
+                stdout.flush()?;
+                y = self.position().1;
+            }
+
+            self.rendered_rows = 0;
+            self.redraw(&mut stdout, &prompt, y)?;
+            stdout.flush()?;
+        }
+
+        Ok(result)
+    }
+
+    /// Calls `on_push` with every line stored in the history, right after it got persisted. Lines
+    /// skipped as duplicates don't trigger it. It runs as soon as a line is submitted, before the
+    /// line gets parsed and handed back by `next_input`.
+    pub fn on_history_push(&mut self, on_push: impl FnMut(&str) + Send + 'static) {
+        self.history.on_push(on_push);
+    }
+
+    /// Runs the line being typed through `P` the same way submitting it would, without submitting
+    /// it or touching the history. Fails when the line isn't a command or doesn't parse.
+    pub fn try_parse_current<P: Parser>(&self) -> Result<P, String> {
+        let cmd = self
+            .command_line(&self.buffer)
+            .ok_or_else(|| "not a command".to_string())?;
+        let mut args = vec![self.program_name()];
+        args.extend(self.options.arg_splitter.split(cmd));
+
+        P::try_parse_from(args).map_err(|e| e.to_string())
+    }
+
+    /// Pushes `entries` to the history, oldest first, as if they had been typed: a line repeating
+    /// the one before is skipped, the backend persists them and `on_history_push` gets called. Use
+    /// an in-memory history to keep them for the session only.
+    pub fn preload_history(
+        &mut self,
+        entries: impl IntoIterator<Item = impl Into<String>>,
+    ) -> io::Result<()> {
+        for entry in entries {
+            self.history.push(entry.into())?;
+        }
+
+        Ok(())
+    }
+
+    /// The `n` most recent history entries, newest first.
+    pub fn recent(&self, n: usize) -> impl Iterator<Item = &str> {
+        self.history.recent(n).map(|entry| entry.line.as_str())
+    }
+
+    /// Same as `recent`, along with how running each entry went when that was recorded.
+    pub fn recent_entries(&self, n: usize) -> impl Iterator<Item = &HistoryEntry> {
+        self.history.recent(n)
+    }
+
+    /// Records how running the last history entry went, once the application is done with it. The
+    /// backend persists it along with the entry.
+    pub fn annotate_last(&mut self, meta: EntryMeta) -> io::Result<()> {
+        self.history.annotate_last(meta)
+    }
+
+    /// Index of the history entry Up and Down navigation is on. It equals `history_len` while a
+    /// fresh line is being typed.
+    pub fn history_cursor(&self) -> usize {
+        self.history.cursor()
+    }
+
+    /// Number of entries in the history.
+    pub fn history_len(&self) -> usize {
+        self.history.len()
+    }
+
+    /// Moves the history navigation back past the newest entry, as submitting a line does.
+    pub fn reset_history_cursor(&mut self) {
+        self.history.reset_cursor();
+    }
+
+    /// Discards the line being typed. While a prompt is on screen, it's drawn again with an empty
+    /// line. Otherwise nothing is printed and the next prompt simply starts from an empty line.
+    pub fn reset_line(&mut self) -> io::Result<()> {
+        self.buffer.clear();
+        self.offset = 0;
+        self.inflight_buffer = None;
+        self.highlight.clear();
+        self.cycle = None;
+
+        if let (Some(prompt), Some(y)) = (self.active_prompt.clone(), self.prompt_row) {
+            let mut stdout = self.out.clone();
+
+            self.redraw(&mut stdout, &prompt, y)?;
+            stdout.flush()?;
+        }
+
+        Ok(())
+    }
+
+    /// Draws the prompt and the line being typed again where glyph last put them, for when
+    /// something else wrote over them. Does nothing when no prompt is active.
+    pub fn refresh(&mut self) -> io::Result<()> {
+        if let (Some(prompt), Some(y)) = (self.active_prompt.clone(), self.prompt_row) {
+            let mut stdout = self.out.clone();
+
+            self.redraw(&mut stdout, &prompt, y)?;
+            stdout.flush()?;
+        }
+
+        Ok(())
+    }
+
+    /// Waits for a single keypress, leaving the line being edited, the history and the prompt
+    /// alone. Handy for yes/no questions or picking an entry from a short menu.
+    pub fn read_key(&mut self) -> io::Result<KeyEvent> {
+        let raw = terminal::is_raw_mode_enabled()?;
+
+        if !raw {
+            self.enter_raw_mode()?;
+        }
+
+        let key = loop {
+            match self.events.read() {
+                Ok(Event::Key(key)) => break Ok(key),
+                Ok(_) => continue,
+                Err(e) => break Err(e),
+            }
+        };
+
+        if !raw {
+            self.leave_raw_mode()?;
+        }
+
+        key
+    }
+
+    /// Asks a yes/no question and waits for `y` or `n`. Enter picks `default`, and the interrupt
+    /// key answers no. Any other key is ignored.
+    pub fn confirm(&mut self, message: &str, default: bool) -> io::Result<bool> {
+        let mut stdout = self.out.clone();
+        let hint = if default { "[Y/n]" } else { "[y/N]" };
+
+        write!(stdout, "{} {}{}", message, hint, self.options.prompt_suffix)?;
+        stdout.flush()?;
+
+        let answer = loop {
+            let key = self.read_key()?;
+            let plain = !key
+                .modifiers
+                .intersects(KeyModifiers::CONTROL | KeyModifiers::ALT);
+
+            match key.code {
+                KeyCode::Char('y') | KeyCode::Char('Y') if plain => break true,
+                KeyCode::Char('n') | KeyCode::Char('N') if plain => break false,
+                KeyCode::Enter => break default,
+                _ if self.is_interrupt(key.code, key.modifiers) => break false,
+                _ => {}
+            }
+        };
+
+        write!(stdout, "{}\r\n", if answer { "y" } else { "n" })?;
+        stdout.flush()?;
+
+        Ok(answer)
+    }
+
+    /// Lists `items` one per row and lets the user pick one with Up and Down. Enter returns the
+    /// index of the highlighted item, while Esc and the interrupt key return `None`.
+    pub fn select(&mut self, items: &[impl Display]) -> io::Result<Option<usize>> {
+        if items.is_empty() {
+            return Ok(None);
+        }
+
+        let mut stdout = self.out.clone();
+        let raw = terminal::is_raw_mode_enabled()?;
+        let mut current = 0;
+
+        // Shows the cursor again if anything goes wrong, and leaves raw mode if it was entered here.
+        let guard = TerminalGuard::new(
+            (!raw && self.options.manage_raw_mode).then_some(self.raw_mode),
+            stdout.clone(),
+            false,
+        );
+
+        if !raw {
+            self.enter_raw_mode()?;
+        }
+
+        queue!(stdout, cursor::Hide)?;
+
+        let selected = loop {
+            for (idx, item) in items.iter().enumerate() {
+                queue!(stdout, Clear(ClearType::CurrentLine))?;
+
+                if idx == current {
+                    queue!(stdout, SetAttribute(Attribute::Reverse))?;
+                    write!(stdout, "> {}", item)?;
+                    queue!(stdout, SetAttribute(Attribute::NoReverse))?;
+                } else {
+                    write!(stdout, "  {}", item)?;
+                }
+
+                write!(stdout, "\r\n")?;
+            }
+
+            stdout.flush()?;
+
+            let key = self.read_key()?;
+
+            match key.code {
+                KeyCode::Up => current = current.saturating_sub(1),
+                KeyCode::Down => current = (current + 1).min(items.len() - 1),
+                KeyCode::Enter => break Some(current),
+                KeyCode::Esc => break None,
+                _ if self.is_interrupt(key.code, key.modifiers) => break None,
+                _ => {}
+            }
+
+            queue!(stdout, cursor::MoveToPreviousLine(items.len() as u16))?;
+        };
+
+        queue!(stdout, cursor::Show)?;
+        stdout.flush()?;
+        guard.complete();
+
+        Ok(selected)
+    }
+
+    /// Hands `input` back so the next call returns it again instead of reading a new line. Inputs
+    /// are given back in reverse order, and only to calls producing the same command type.
+    pub fn unget<C: Send + 'static>(&mut self, input: Input<C>) {
+        self.pending.push(Box::new(input));
+    }
+
+    pub fn next_input(&mut self) -> io::Result<Option<Input<Params>>> {
+        self.next_input_with_options(&Default::default())
+    }
+
+    pub fn next_input_with_options(
+        &mut self,
+        options: &PromptOptions,
+    ) -> io::Result<Option<Input<Params>>> {
+        self.next_input_with(options, |args| Ok::<_, String>(Params::new(args)))
+    }
+
+    pub fn next_input_with_parser<P: Parser + 'static>(&mut self) -> io::Result<Option<Input<P>>> {
+        self.next_input_with_parser_and_options::<P>(&Default::default())
+    }
+
+    pub fn next_input_with_parser_and_options<P: Parser + 'static>(
+        &mut self,
+        options: &PromptOptions,
+    ) -> io::Result<Option<Input<P>>> {
+        let parser = self.clap_parser::<P>();
+
+        self.next_input_with(options, parser)
+    }
+
+    /// Same as `next_input_with_parser`, along with clap's matches, which tell the arguments passed
+    /// explicitly from the defaulted ones.
+    pub fn next_input_with_matches<P: Parser + 'static>(
+        &mut self,
+        options: &PromptOptions,
+    ) -> io::Result<Option<Input<(P, ArgMatches)>>> {
+        let program_name = self.program_name();
+
+        self.next_input_with(options, move |args| {
+            let mut updated = vec![program_name.clone()];
+            updated.extend(args);
+
+            let matches = P::command().try_get_matches_from(updated)?;
+            let parsed = P::from_arg_matches(&matches).map_err(|e| e.format(&mut P::command()))?;
+
+            Ok::<_, clap::Error>((parsed, matches))
+        })
+    }
+
+    /// Same as `next_input_with_parser`, also telling what the line looked like and whether it
+    /// was read as a command.
+    pub fn next_submission_with_parser<P: Parser + 'static>(
+        &mut self,
+        options: &PromptOptions,
+    ) -> io::Result<Option<Submission<P>>> {
+        let parser = self.clap_parser::<P>();
+
+        self.next_submission_with(options, parser)
+    }
+
+    /// Same as `next_input_with`, also telling what the line looked like and whether it was read
+    /// as a command.
+    pub fn next_submission_with<F, E, C>(
+        &mut self,
+        options: &PromptOptions,
+        parser: F,
+    ) -> io::Result<Option<Submission<C>>>
+    where
+        C: 'static,
+        E: Display,
+        F: Fn(Vec<String>) -> Result<C, E>,
+    {
+        self.submitted = None;
+
+        let input = self.next_input_with(options, parser)?;
+        let (raw, was_command) = self.submitted.take().unwrap_or_default();
+
+        Ok(input.map(|input| Submission {
+            input,
+            was_command,
+            raw,
+        }))
+    }
+
+    fn clap_parser<P: Parser>(&self) -> impl Fn(Vec<String>) -> Result<P, clap::Error> {
+        let cmd_prompt = self.program_name();
+
+        move |args| {
+            let mut updated = vec![cmd_prompt.clone()];
+            updated.extend(args);
+            P::try_parse_from(updated)
+        }
+    }
+
+    pub fn next_input_parsed<C>(&mut self) -> io::Result<Option<Input<C>>>
+    where
+        C: FromStr + 'static,
+        C::Err: Display,
+    {
+        self.next_input_parsed_with_options(&Default::default())
+    }
+
+    pub fn next_input_parsed_with_options<C>(
+        &mut self,
+        options: &PromptOptions,
+    ) -> io::Result<Option<Input<C>>>
+    where
+        C: FromStr + 'static,
+        C::Err: Display,
+    {
+        // The command goes to `from_str` as typed, quotes and line breaks included.
+        self.read_command(options, |cmd| C::from_str(cmd).into())
+    }
+
+    pub fn next_input_with<F, E, C>(
+        &mut self,
+        options: &PromptOptions,
+        parser: F,
+    ) -> io::Result<Option<Input<C>>>
+    where
+        C: 'static,
+        E: Display,
+        F: Fn(Vec<String>) -> Result<C, E>,
+    {
+        self.next_input_with_outcome(options, |args| parser(args).into())
+    }
+
+    /// Same as `next_input_with`, the parser also deciding when a command isn't over yet. On
+    /// `ParseOutcome::Incomplete`, Enter starts a continuation line instead of submitting, and
+    /// the whole text goes through the parser again on the next Enter.
+    pub fn next_input_with_outcome<F, E, C>(
+        &mut self,
+        options: &PromptOptions,
+        parser: F,
+    ) -> io::Result<Option<Input<C>>>
+    where
+        C: 'static,
+        E: Display,
+        F: Fn(Vec<String>) -> ParseOutcome<C, E>,
+    {
+        let splitter = self.options.arg_splitter.clone();
+
+        self.read_command(options, move |cmd| parser(splitter.split(cmd)))
+    }
+
+    // Reads the next input, `parser` getting the text of commands, without their prefix.
+    fn read_command<F, E, C>(
+        &mut self,
+        options: &PromptOptions,
+        parser: F,
+    ) -> io::Result<Option<Input<C>>>
+    where
+        C: 'static,
+        E: Display,
+        F: Fn(&str) -> ParseOutcome<C, E>,
+    {
+        // Raw mode, entered once the prompt shows up, is left whichever way the call ends.
+        let guard = TerminalGuard::new(
+            self.options.manage_raw_mode.then_some(self.raw_mode),
+            self.out.clone(),
+            self.alternate_screen,
+        );
+        let input = self.read_input(options, parser);
+
+        if input.is_ok() {
+            guard.complete();
+        } else {
+            // Left by the guard, along with the rest of what the read may have changed.
+            self.alternate_screen = false;
+        }
+
+        if self.terminated {
+            self.leave_alternate_screen()?;
+        }
+
+        input
+    }
+
+    fn read_input<F, E, C>(
+        &mut self,
+        options: &PromptOptions,
+        parser: F,
+    ) -> io::Result<Option<Input<C>>>
+    where
+        C: 'static,
+        E: Display,
+        F: Fn(&str) -> ParseOutcome<C, E>,
+    {
+        if let Some(idx) = self
+            .pending
+            .iter()
+            .rposition(|input| input.is::<Input<C>>())
+        {
+            if let Ok(input) = self.pending.remove(idx).downcast::<Input<C>>() {
+                return Ok(Some(*input));
+            }
+        }
+
+        if self.terminated {
+            return Ok(None);
+        }
+
+        self.vi.reset();
+
+        let mut prompt = self.build_prompt(options);
+        let mut stdout = self.out.clone();
+
+        // Queued lines the parser found incomplete, waiting for the ones completing them.
+        let mut unfinished = String::new();
+
+        while let Some(line) = self.script.pop_front() {
+            if let Some(prefix) = self.options.script_comments.as_ref() {
+                let trimmed = line.trim_start();
+
+                if trimmed.is_empty() || trimmed.starts_with(prefix.as_str()) {
+                    continue;
+                }
+            }
+
+            let shown = if unfinished.is_empty() {
+                self.options
+                    .transient_prompt
+                    .clone()
+                    .unwrap_or(prompt.clone())
+            } else {
+                self.continuation_prompt()
+            };
+
+            writeln!(stdout, "{}{}", shown, line)?;
+            stdout.flush()?;
+
+            let line = if unfinished.is_empty() {
+                line
+            } else {
+                format!("{}\n{}", std::mem::take(&mut unfinished), line)
+            };
+
+            match self.interpret(&line, &parser)? {
+                Interpreted::Skip => {}
+                Interpreted::Incomplete => unfinished = line,
+                Interpreted::Invalid(e) if self.options.return_parse_errors => {
+                    return Ok(Some(Input::Error {
+                        line: line.trim().to_string(),
+                        error: e.to_string(),
+                    }));
+                }
+                Interpreted::Invalid(e) => {
+                    match self.error_renderer.as_ref() {
+                        Some(render) => render(&e.to_string()),
+                        None => writeln!(stdout, "{}", e)?,
+                    }
+
+                    stdout.flush()?;
+                }
+                Interpreted::Accepted(input) => return Ok(Some(input)),
+            }
+        }
+
+        if self.options.exit_after_script {
+            self.terminated = true;
+            return Ok(Some(Input::Exit));
+        }
+
+        // What's left unfinished once the script ran out is typed on.
+        if !unfinished.is_empty() {
+            unfinished.push('\n');
+            self.buffer = unfinished;
+            self.set_cursor(self.buffer.len());
+        }
+
+        self.enter_raw_mode()?;
+
+        let (x, y) = self.position();
+
+        // When the cursor already sits at the start of a row, that row is considered fresh and the
+        // prompt is drawn on it instead of leaving a blank line behind.
+        let y = if self.options.compact_prompt && x == 0 {
+            y
+        } else {
+            y + 1
+        };
+
+        self.active_prompt = Some(prompt.clone());
+        self.rendered_rows = 0;
+        self.redraw(&mut stdout, &prompt, y)?;
+
+        stdout.flush()?;
+
+        let deadline = options.timeout.map(|timeout| Instant::now() + timeout);
+        let mut deferred = false;
+
+        loop {
+            let interval = self
+                .options
+                .live_prompt
+                .as_ref()
+                .map(|(interval, _)| *interval);
+            let remaining =
+                deadline.map(|deadline| deadline.saturating_duration_since(Instant::now()));
+            let printing = self.printer.as_ref().map(|_| PRINTER_INTERVAL);
+            let wait = [interval, remaining, printing].into_iter().flatten().min();
+
+            if let Some(wait) = wait {
+                let ready = self.events.poll(wait)?;
+
+                // Printed along with whatever the event draws, or right away when it's the only
+                // thing to draw.
+                if let Some((_, messages)) = self.printer.as_ref() {
+                    let messages = messages.try_iter().collect::<Vec<_>>();
+
+                    for message in messages {
+                        self.queue_above(message)?;
+                    }
+                }
+
+                if !ready {
+                    // The input is wiped off the screen, the caller's output taking its place, and
+                    // drawn again by the next call with the line as it was left.
+                    if deadline.is_some_and(|deadline| Instant::now() >= deadline) {
+                        let y = self.prompt_row.unwrap_or(y);
+
+                        queue!(stdout, MoveTo(0, y), Clear(ClearType::FromCursorDown))?;
+                        stdout.flush()?;
+
+                        self.rendered_rows = 0;
+                        self.active_prompt = None;
+
+                        return Ok(Some(Input::Timeout));
+                    }
+
+                    let updated = self.build_prompt(options);
+
+                    if updated != prompt && self.search.is_none() {
+                        prompt = updated;
+                        self.active_prompt = Some(prompt.clone());
+
+                        if let Some(y) = self.prompt_row {
+                            self.redraw(&mut stdout, &prompt, y)?;
+                        }
+                    }
+
+                    stdout.flush()?;
+                    continue;
+                }
+            }
+
+            let c = self.events.read()?;
+
+            // Characters coming in a row, like when pasting, are drawn in one go after the last one.
+            if std::mem::take(&mut deferred) && !self.inserts_plainly(&c) {
+                self.redraw(&mut stdout, &prompt, self.prompt_row.unwrap_or(y))?;
+            }
+
+            // The input row is kept up to date by `redraw`, even when the terminal scrolls, so the
+            // terminal only gets asked again once resized, as it may have reflowed the input.
+            if let Event::Resize(_, _) = c {
+                if self.options.assume_position.is_none() {
+                    if let Ok((_, row)) = self.events.cursor_position() {
+                        let row = row.saturating_sub(self.cursor_position(&prompt).1);
+                        self.prompt_row = Some(row);
+                    }
+                }
+
+                continue;
+            }
+
+            let y = self.prompt_row.unwrap_or(y);
+
+            if let Event::Key(KeyEvent { code, modifiers }) = c {
+                let interrupted = std::mem::take(&mut self.interrupted);
+                let last_arg = self.last_arg.take();
+                let killing = std::mem::take(&mut self.killing);
+                let yanked = self.yanked.take();
+
+                if self.search.is_some()
+                    && self.handle_search_key(&mut stdout, &prompt, y, code, modifiers)?
+                {
+                    stdout.flush()?;
+                    continue;
+                }
+
+                if std::mem::take(&mut self.ctrl_x)
+                    && code == KeyCode::Char('e')
+                    && modifiers.contains(KeyModifiers::CONTROL)
+                {
+                    self.edit_externally()?;
+                    self.redraw(&mut stdout, &prompt, self.prompt_row.unwrap_or(y))?;
+                    self.buffer_changed();
+                    stdout.flush()?;
+                    continue;
+                }
+
+                // Alt followed by digits sets how many times the next motion or deletion applies.
+                let repeat = match code {
+                    KeyCode::Char(c)
+                        if modifiers.contains(KeyModifiers::ALT) && c.is_ascii_digit() =>
+                    {
+                        let digit = c.to_digit(10).unwrap_or_default() as usize;
+                        self.count = Some((self.count.unwrap_or(0) * 10 + digit).min(MAX_COUNT));
+                        continue;
+                    }
+
+                    _ => self.count.take().unwrap_or(1),
+                };
+
+                let vi_key = if self.options.vi_mode {
+                    let mode = self.vi.mode();
+                    let vi_key = self.vi.key(code, modifiers);
+
+                    if self.vi.mode() != mode {
+                        prompt = self.build_prompt(options);
+                        self.active_prompt = Some(prompt.clone());
+                        self.redraw(&mut stdout, &prompt, y)?;
+                    }
+
+                    vi_key
+                } else {
+                    ViKey::Keymap
+                };
+
+                // Keys bound in the keymap win over the interrupt key.
+                let action = match vi_key {
+                    ViKey::Perform(action) => action,
+                    ViKey::Ignore => {
+                        stdout.flush()?;
+                        continue;
+                    }
+                    ViKey::Keymap => match self.options.keymap.action(code, modifiers) {
+                        Some(action) => action.clone(),
+                        None if self.is_interrupt(code, modifiers) => Action::Interrupt,
+                        None => match code {
+                            KeyCode::Char(c) => Action::InsertChar(c),
+                            _ => continue,
+                        },
+                    },
+                };
+
+                if !matches!(
+                    action,
+                    Action::Tab | Action::Complete | Action::CompleteBackward
+                ) {
+                    self.cycle = None;
+                }
+
+                match action {
+                    // A statement spanning several lines is abandoned rather than the session.
+                    Action::Interrupt if self.buffer.contains('\n') => {
+                        self.cancel_line(&mut stdout, &prompt, y)?;
+                    }
+
+                    Action::Interrupt if self.options.double_ctrl_c_exits && !interrupted => {
+                        self.cancel_line(&mut stdout, &prompt, y)?;
+                        self.interrupted = true;
+                    }
+
+                    Action::Interrupt => {
+                        queue!(stdout, MoveTo(0, self.last_row(y)))?;
+                        writeln!(stdout)?;
+                        stdout.flush()?;
+                        self.terminated = true;
+                        self.active_prompt = None;
+                        return Ok(Some(Input::Exit));
+                    }
+
+                    Action::ReverseSearch => {
+                        self.search = Some(Search::new(self.buffer.clone(), self.offset));
+                        self.redraw_search(&mut stdout, y)?;
+                    }
+
+                    Action::EditorPrefix => {
+                        self.ctrl_x = true;
+                    }
+
+                    Action::EditExternally => {
+                        self.edit_externally()?;
+                        self.redraw(&mut stdout, &prompt, self.prompt_row.unwrap_or(y))?;
+                        self.buffer_changed();
+                    }
+
+                    Action::MoveHome => {
+                        self.offset = 0;
+                        self.redraw(&mut stdout, &prompt, y)?;
+                    }
+
+                    Action::MoveEnd => {
+                        self.set_cursor(self.buffer.len());
+                        self.redraw(&mut stdout, &prompt, y)?;
+                    }
+
+                    Action::DeleteWordBackward => {
+                        self.delete_words_before(repeat, killing);
+                        self.redraw(&mut stdout, &prompt, y)?;
+
+                        self.buffer_changed();
+                    }
+
+                    Action::DeleteBigWordBackward => {
+                        let end = self.cursor();
+                        // What's before the cursor stays the same, each word going in turn.
+                        let offset = (0..repeat).fold(self.offset, |offset, _| {
+                            big_word_start(&self.buffer, offset)
+                        });
+
+                        self.kill(byte_index(&self.buffer, offset)..end, killing);
+                        self.redraw(&mut stdout, &prompt, y)?;
+
+                        self.buffer_changed();
+                    }
+
+                    Action::DeleteWordForward => {
+                        self.delete_words_after(repeat, killing);
+                        self.redraw(&mut stdout, &prompt, y)?;
+
+                        self.buffer_changed();
+                    }
+
+                    Action::KillToEnd => {
+                        let start = self.cursor();
+                        let end = match self.buffer[start..].find('\n') {
+                            Some(0) => start + 1,
+                            Some(idx) => start + idx,
+                            None => self.buffer.len(),
+                        };
+
+                        self.kill(start..end, killing);
+                        self.redraw(&mut stdout, &prompt, y)?;
+
+                        self.buffer_changed();
+                    }
+
+                    Action::KillToStart => {
+                        let end = self.cursor();
+                        let start = self.buffer[..end].rfind('\n').map_or(0, |idx| idx + 1);
+
+                        self.kill(start..end, killing);
+                        self.redraw(&mut stdout, &prompt, y)?;
+
+                        self.buffer_changed();
+                    }
+
+                    Action::Yank => {
+                        if let Some(text) = self.kill_ring.yank().map(str::to_string) {
+                            self.yank(self.cursor()..self.cursor(), &text);
+                            self.redraw(&mut stdout, &prompt, y)?;
+
+                            self.buffer_changed();
+                        }
+                    }
+
+                    Action::YankPop => {
+                        if let Some(range) = yanked {
+                            match self.kill_ring.rotate().map(str::to_string) {
+                                Some(text) => self.yank(range, &text),
+                                None => self.yanked = Some(range),
+                            }
+
+                            self.redraw(&mut stdout, &prompt, y)?;
+
+                            self.buffer_changed();
+                        }
+                    }
+
+                    Action::Backspace => {
+                        self.delete_before_cursor(repeat);
+                        self.redraw(&mut stdout, &prompt, y)?;
+
+                        self.buffer_changed();
+                    }
+
+                    Action::Delete if self.cursor() < self.buffer.len() => {
+                        let start = self.cursor();
+                        let end = chars_forward(&self.buffer, start, repeat);
+
+                        self.buffer.drain(start..end);
+                        self.redraw(&mut stdout, &prompt, y)?;
+
+                        self.buffer_changed();
+                    }
+
+                    // Whitespace separates words here, `foo/bar.baz` being jumped over in one go.
+                    Action::MoveWordLeft => {
+                        let start = self.cursor();
+                        let start = (0..repeat).fold(start, |pos, _| word_start(&self.buffer, pos));
+
+                        self.set_cursor(start);
+                        self.redraw(&mut stdout, &prompt, y)?;
+                    }
+
+                    Action::MoveWordRight => {
+                        let end = self.cursor();
+                        let end = (0..repeat).fold(end, |pos, _| word_end(&self.buffer, pos));
+
+                        self.set_cursor(end);
+                        self.redraw(&mut stdout, &prompt, y)?;
+                    }
+
+                    Action::MoveLeft if self.offset > 0 => {
+                        self.set_cursor(chars_back(&self.buffer, self.cursor(), repeat));
+                        self.redraw(&mut stdout, &prompt, y)?;
+                    }
+
+                    Action::MoveRight if self.cursor() < self.buffer.len() => {
+                        self.set_cursor(chars_forward(&self.buffer, self.cursor(), repeat));
+                        self.redraw(&mut stdout, &prompt, y)?;
+                    }
+
+                    Action::HistoryPrevious | Action::HistoryNext if options.no_history_nav => {}
+
+                    Action::HistoryPrevious => {
+                        let from = self.history.cursor();
+
+                        if let Some(entry) = self.history.prev_entry() {
+                            self.recall(from, entry);
+                            self.redraw(&mut stdout, &prompt, y)?;
+                        }
+                    }
+
+                    Action::HistoryNext => {
+                        let from = self.history.cursor();
+                        let entry = self
+                            .history
+                            .next_entry()
+                            .or_else(|| self.inflight_buffer.clone())
+                            .unwrap_or_default();
+
+                        self.recall(from, entry);
+                        self.redraw(&mut stdout, &prompt, y)?;
+                    }
+
+                    Action::InsertNewline => {
+                        self.insert_char('\n');
+                        self.redraw(&mut stdout, &prompt, y)?;
+
+                        self.buffer_changed();
+                    }
+
+                    Action::Submit => {
+                        if let Err(e) = self.validate_last_line() {
+                            self.report(e)?;
+                        } else if self.needs_continuation() {
+                            self.buffer.push('\n');
+                            self.set_cursor(self.buffer.len());
+                            self.buffer_changed();
+                            self.redraw(&mut stdout, &prompt, y)?;
+                        } else {
+                            if let Some(transient) = self.options.transient_prompt.clone() {
+                                self.redraw(&mut stdout, &transient, y)?;
+                            }
+
+                            let line = std::mem::take(&mut self.buffer);
+                            self.offset = 0;
+
+                            match self.interpret(&line, &parser)? {
+                                Interpreted::Incomplete => {
+                                    self.buffer = line;
+                                    self.buffer.push('\n');
+                                    self.set_cursor(self.buffer.len());
+                                    self.buffer_changed();
+                                    self.redraw(&mut stdout, &prompt, y)?;
+                                }
+
+                                Interpreted::Skip => {
+                                    queue!(stdout, MoveTo(0, self.last_row(y)))?;
+                                    writeln!(stdout)?;
+                                    queue!(stdout, MoveToNextLine(1))?;
+
+                                    let y = self.row_below(self.last_row(y), 2);
+                                    self.rendered_rows = 0;
+                                    self.redraw(&mut stdout, &prompt, y)?;
+                                }
+
+                                Interpreted::Invalid(e) if self.options.return_parse_errors => {
+                                    self.finish_line(&mut stdout, y)?;
+
+                                    return Ok(Some(Input::Error {
+                                        line: line.trim().to_string(),
+                                        error: e.to_string(),
+                                    }));
+                                }
+
+                                Interpreted::Invalid(e) => {
+                                    self.report_error(e)?;
+                                }
+
+                                Interpreted::Accepted(input) => {
+                                    self.finish_line(&mut stdout, y)?;
+
+                                    return Ok(Some(input));
+                                }
+                            }
+                        }
+                    }
+
+                    Action::Tab if self.tab_behavior() == TabBehavior::Insert => {
+                        let line = self.buffer[..self.cursor()].rsplit('\n').next();
+                        let column = display_width(line.unwrap_or_default());
+
+                        for _ in 0..TAB_WIDTH - column % TAB_WIDTH {
+                            self.insert_char(' ');
+                        }
+
+                        self.redraw(&mut stdout, &prompt, y)?;
+
+                        self.buffer_changed();
+                    }
+
+                    Action::Tab if self.tab_behavior() == TabBehavior::Ignore => {}
+
+                    Action::Tab | Action::Complete | Action::CompleteBackward => {
+                        if let Some(listing) = self.complete(action == Action::CompleteBackward) {
+                            self.list_completions(&listing)?;
+                        } else {
+                            self.redraw(&mut stdout, &prompt, y)?;
+                        }
+
+                        self.buffer_changed();
+                    }
+
+                    Action::InsertVerbatim => {
+                        if let Some(c) = read_verbatim(&self.events)? {
+                            self.insert_char(c);
+                            self.redraw(&mut stdout, &prompt, y)?;
+
+                            self.buffer_changed();
+                        }
+                    }
+
+                    Action::Escape => match self.options.esc_action {
+                        EscAction::Ignore => {}
+
+                        EscAction::ClearLine => {
+                            self.buffer.clear();
+                            self.offset = 0;
+                            self.redraw(&mut stdout, &prompt, y)?;
+
+                            self.buffer_changed();
+                        }
+
+                        EscAction::Cancel => self.cancel_line(&mut stdout, &prompt, y)?,
+                    },
+
+                    Action::ClearLine => {
+                        self.buffer.clear();
+                        self.offset = 0;
+                        self.redraw(&mut stdout, &prompt, y)?;
+
+                        self.buffer_changed();
+                    }
+
+                    Action::CancelLine => self.cancel_line(&mut stdout, &prompt, y)?,
+
+                    // Inserts the last word of the previous entry. Pressing it again swaps that
+                    // word for the last word of the entry before, and so on.
+                    Action::InsertLastArg => {
+                        let nth = last_arg.as_ref().map_or(0, |(nth, _)| nth + 1);
+                        let word = self.history.recent(nth + 1).nth(nth).map(|entry| {
+                            entry
+                                .line
+                                .split_whitespace()
+                                .last()
+                                .unwrap_or_default()
+                                .to_string()
+                        });
+
+                        match word {
+                            Some(word) => {
+                                let start = match last_arg {
+                                    Some((_, range)) => {
+                                        self.buffer.drain(range.clone());
+                                        range.start
+                                    }
+
+                                    None => self.cursor(),
+                                };
+
+                                self.buffer.insert_str(start, &word);
+                                self.set_cursor(start + word.len());
+                                self.last_arg = Some((nth, start..start + word.len()));
+                            }
+
+                            // Past the oldest entry, the word inserted last stays.
+                            None => self.last_arg = last_arg,
+                        }
+
+                        self.redraw(&mut stdout, &prompt, y)?;
+
+                        self.buffer_changed();
+                    }
+
+                    Action::UppercaseWord => {
+                        self.convert_words(repeat, str::to_uppercase);
+                        self.redraw(&mut stdout, &prompt, y)?;
+
+                        self.buffer_changed();
+                    }
+
+                    Action::LowercaseWord => {
+                        self.convert_words(repeat, str::to_lowercase);
+                        self.redraw(&mut stdout, &prompt, y)?;
+
+                        self.buffer_changed();
+                    }
+
+                    Action::CapitalizeWord => {
+                        self.convert_words(repeat, capitalize);
+                        self.redraw(&mut stdout, &prompt, y)?;
+
+                        self.buffer_changed();
+                    }
+
+                    Action::ToggleOverwrite => {
+                        self.overwrite = !self.overwrite;
+                    }
+
+                    Action::InsertSnippet { text, cursor_at } => {
+                        let start = self.cursor();
+                        let cursor = cursor_at
+                            .and_then(|n| text.char_indices().nth(n).map(|(idx, _)| idx))
+                            .unwrap_or(text.len());
+
+                        self.buffer.insert_str(start, &text);
+                        self.set_cursor(start + cursor);
+                        self.redraw(&mut stdout, &prompt, y)?;
+
+                        self.buffer_changed();
+                    }
+
+                    Action::InsertChar(c) => {
+                        // In overwrite mode, the typed character replaces the one under the
+                        // cursor, unless the cursor is at the end of its line.
+                        if self.overwrite
+                            && self.buffer[self.cursor()..]
+                                .chars()
+                                .next()
+                                .is_some_and(|next| next != '\n')
+                        {
+                            self.buffer.remove(self.cursor());
+                        }
+
+                        self.insert_char(c);
+                        self.buffer_changed();
+
+                        if self.events.poll(Duration::ZERO)? {
+                            deferred = true;
+                        } else {
+                            self.redraw(&mut stdout, &prompt, y)?;
+                        }
+                    }
+                    _ => {}
+                }
+            }
+
+            stdout.flush()?;
+        }
+    }
+
+    // Handles a key typed while searching, telling whether the search used it. Keys it doesn't
+    // use end the search and are left to do what they usually do.
+    fn handle_search_key(
+        &mut self,
+        stdout: &mut impl Write,
+        prompt: &str,
+        y: u16,
+        code: KeyCode,
+        modifiers: KeyModifiers,
+    ) -> io::Result<bool> {
+        let interrupt = self.is_interrupt(code, modifiers);
+        let search = if let Some(search) = self.search.as_mut() {
+            search
+        } else {
+            return Ok(false);
+        };
+
+        let mut older = false;
+
+        match code {
+            // Gives up, the line being back to what it was before searching.
+            _ if code == KeyCode::Esc || interrupt => {
+                let (buffer, offset) = search.saved();
+                self.buffer = buffer.to_string();
+                self.offset = offset;
+                self.search = None;
+                self.highlight.clear();
+                self.redraw(stdout, prompt, y)?;
+
+                return Ok(true);
+            }
+
+            KeyCode::Char(c) if !modifiers.contains(KeyModifiers::CONTROL) => search.push(c),
+            KeyCode::Backspace => search.pop(),
+
+            // Goes on with older entries.
+            KeyCode::Char('r') if modifiers.contains(KeyModifiers::CONTROL) => older = true,
+
+            _ => {
+                // Any other key ends the search, keeping the line that was found.
+                self.search = None;
+                self.highlight.clear();
+                self.buffer_changed();
+                self.redraw(stdout, prompt, y)?;
+
+                return Ok(false);
+            }
+        }
+
+        if let Some((entry, ranges)) = search.find(&self.history, self.options.search_mode, older) {
+            self.buffer = entry;
+            self.set_cursor(ranges.first().map_or(0, |range| range.start));
+            self.highlight = ranges;
+        } else if search.query().is_empty() {
+            let (buffer, offset) = search.saved();
+            self.highlight.clear();
+            self.offset = offset;
+            self.buffer = buffer.to_string();
+        }
+
+        self.redraw_search(stdout, y)?;
+
+        Ok(true)
+    }
+
+    fn redraw_search(&mut self, stdout: &mut impl Write, y: u16) -> io::Result<()> {
+        let query = self.search.as_ref().map(Search::query).unwrap_or_default();
+        let prompt = format!("{}'{}': ", self.options.search_prompt, query);
+
+        self.redraw(stdout, &prompt, y)
+    }
+
+    // Completes the token under the cursor. When the candidates have to be shown to the user, the
+    // listing is returned instead.
+    fn tab_behavior(&self) -> TabBehavior {
+        match self.options.tab_behavior {
+            Some(behavior) => behavior,
+            None if self.options.completer.is_some() => TabBehavior::Complete,
+            None => TabBehavior::Ignore,
+        }
+    }
+
+    fn complete(&mut self, backward: bool) -> Option<Vec<String>> {
+        let completer = self.options.completer.clone()?;
+
+        if let Some(cycle) = self.cycle.as_mut() {
+            let len = cycle.candidates.len();
+            cycle.index = if backward {
+                (cycle.index + len - 1) % len
+            } else {
+                (cycle.index + 1) % len
+            };
+
+            let candidate = cycle.candidates[cycle.index].clone();
+            self.buffer = cycle.buffer.clone();
+            self.offset = cycle.offset;
+            self.apply_completion(&candidate);
+
+            return None;
+        }
+
+        let candidates = self.candidates(completer.0.as_ref());
+
+        match candidates.as_slice() {
+            [] => return None,
+            [candidate] => {
+                self.apply_completion(candidate);
+                return None;
+            }
+            _ => {}
+        }
+
+        if let Some(prefix) = common_prefix(&self.buffer, &candidates) {
+            self.apply_completion(&prefix);
+            return None;
+        }
+
+        match self.options.completion_style {
+            CompletionStyle::Menu => Some(candidates.into_iter().map(|c| c.display).collect()),
+
+            CompletionStyle::Cycle => {
+                let index = if backward { candidates.len() - 1 } else { 0 };
+                let candidate = candidates[index].clone();
+
+                self.cycle = Some(Cycle {
+                    buffer: self.buffer.clone(),
+                    offset: self.offset,
+                    candidates,
+                    index,
+                });
+
+                self.apply_completion(&candidate);
+
+                None
+            }
+        }
+    }
+
+    // Candidates for the token under the cursor. With a fuzzy search mode, they're filtered here
+    // rather than by the completer, the text they replace having to match them, best matches
+    // first.
+    fn candidates(&self, completer: &(dyn Completer + Send + Sync)) -> Vec<Completion> {
+        match self.options.search_mode {
+            SearchMode::Exact => completer.complete(&self.buffer, self.cursor()),
+
+            #[cfg(feature = "fuzzy")]
+            SearchMode::Fuzzy => {
+                let mut scored = completer
+                    .complete_unfiltered(&self.buffer, self.cursor())
+                    .into_iter()
+                    .filter_map(|candidate| {
+                        let typed = self.buffer.get(candidate.span.clone()).unwrap_or_default();
+
+                        if typed.is_empty() {
+                            return Some((0, candidate));
+                        }
+
+                        search::fuzzy_match(typed, &candidate.replacement)
+                            .map(|(score, _)| (score, candidate))
+                    })
+                    .collect::<Vec<_>>();
+
+                // Stable, so candidates scoring the same keep the completer order.
+                scored.sort_by_key(|(score, _)| std::cmp::Reverse(*score));
+                scored.into_iter().map(|(_, candidate)| candidate).collect()
+            }
+        }
+    }
+
+    fn apply_completion(&mut self, completion: &Completion) {
+        let start = completion.span.start;
+        let end = completion.span.end.min(self.buffer.len());
+
+        if start > end || !self.buffer.is_char_boundary(start) || !self.buffer.is_char_boundary(end)
+        {
+            return;
+        }
+
+        self.buffer
+            .replace_range(start..end, &completion.replacement);
+        self.set_cursor(start + completion.replacement.len());
+    }
+
+    // Keeps track of what's typed on the fresh line, so it's still there after browsing the
+    // history. Edits made to recalled entries are saved when navigating away from them instead.
+    fn buffer_changed(&mut self) {
+        if self.history.cursor() < self.history.len() {
+            return;
+        }
+
+        if self.buffer.is_empty() {
+            self.inflight_buffer = None;
+        } else {
+            self.inflight_buffer = Some(self.buffer.clone());
+        }
+    }
+
+    // Shows `entry` after moving away from the history entry at index `from`. Each entry can be
+    // edited independently until the line is submitted, so changes made to the entry we leave are
+    // saved and restored when coming back to it.
+    fn recall(&mut self, from: usize, entry: String) {
+        if let Some(original) = self.history.entries().get(from) {
+            if original.line == self.buffer {
+                self.edits.remove(&from);
+            } else {
+                self.edits
+                    .insert(from, (std::mem::take(&mut self.buffer), self.offset));
+            }
+        }
+
+        if let Some((buffer, offset)) = self.edits.get(&self.history.cursor()) {
+            self.buffer = buffer.clone();
+            self.offset = *offset;
+            return;
+        }
+
+        // The cursor being counted in characters, it keeps its column whatever the entries hold.
+        if !self.options.keep_cursor_column {
+            self.offset = entry.chars().count();
+        }
+
+        self.offset = self.offset.min(entry.chars().count());
+        self.buffer = entry;
+    }
+
+    // Opens the buffer in $VISUAL or $EDITOR and loads back whatever got saved. Leaving the editor
+    // with a failure status keeps the buffer as it was.
+    fn edit_externally(&mut self) -> io::Result<()> {
+        let (path, mut file) = create_scratch_file()?;
+
+        if let Err(e) = file.write_all(self.buffer.as_bytes()) {
+            let _ = fs::remove_file(&path);
+            return Err(e);
+        }
+
+        drop(file);
+
+        let editor = ["VISUAL", "EDITOR"]
+            .iter()
+            .filter_map(|name| env::var(name).ok())
+            .find(|value| !value.trim().is_empty())
+            .unwrap_or_else(|| DEFAULT_EDITOR.to_string());
+
+        let mut out = self.out.clone();
+        let saved = self.with_cooked_mode(|| {
+            let mut words = editor.split_whitespace();
+            let program = words.next().unwrap_or(DEFAULT_EDITOR);
+
+            match Command::new(program).args(words).arg(&path).status() {
+                Ok(status) => status.success(),
+                Err(e) => {
+                    let _ = writeln!(out, "failed to launch editor '{}': {}", editor, e);
+                    false
+                }
+            }
+        });
+
+        let saved = match saved {
+            Ok(saved) => saved,
+            Err(e) => {
+                let _ = fs::remove_file(&path);
+                return Err(e);
+            }
+        };
+
+        if saved {
+            if let Ok(text) = fs::read_to_string(&path) {
+                self.buffer = text.trim_end_matches(['\n', '\r']).to_string();
+                self.set_cursor(self.buffer.len());
+            }
+        }
+
+        let _ = fs::remove_file(&path);
+
+        Ok(())
+    }
+
+    // Removes up to `count` characters before the cursor. At the start of the line, there is
+    // nothing to remove and the buffer is left untouched.
+    fn delete_before_cursor(&mut self, count: usize) {
+        let end = self.cursor();
+
+        if end == 0 || count == 0 {
+            return;
+        }
+
+        let start = chars_back(&self.buffer, end, count);
+
+        self.buffer.drain(start..end);
+        self.set_cursor(start);
+    }
+
+    // Kills from the start of the `count`th word before the cursor up to the cursor, separators
+    // in between included. With the cursor inside a word, only the part before it goes.
+    fn delete_words_before(&mut self, count: usize, accumulate: bool) {
+        let end = self.cursor();
+        let start = (0..count).fold(end, |pos, _| alnum_word_start(&self.buffer, pos));
+
+        self.kill(start..end, accumulate);
+    }
+
+    // Kills from the cursor up to the end of the `count`th word after it.
+    fn delete_words_after(&mut self, count: usize, accumulate: bool) {
+        let start = self.cursor();
+        let end = (0..count).fold(start, |pos, _| alnum_word_end(&self.buffer, pos));
+
+        self.kill(start..end, accumulate);
+    }
+
+    // Removes `range` from the buffer into the kill ring, joining the previous kill when
+    // `accumulate` is set, as for kills coming in a row.
+    fn kill(&mut self, range: Range<usize>, accumulate: bool) {
+        if range.is_empty() {
+            self.killing = accumulate;
+            return;
+        }
+
+        let backward = range.end == self.cursor();
+        let killed = self.buffer.drain(range.clone()).collect::<String>();
+
+        self.kill_ring.kill(&killed, backward, accumulate);
+        self.set_cursor(range.start);
+        self.killing = true;
+    }
+
+    // Writes `text` in place of `range`, leaving the cursor after it.
+    fn yank(&mut self, range: Range<usize>, text: &str) {
+        self.buffer.replace_range(range.clone(), text);
+        self.set_cursor(range.start + text.len());
+        self.yanked = Some(range.start..range.start + text.len());
+    }
+
+    // Rewrites the next `count` words from the cursor with `convert`, then moves the cursor past
+    // them. The converted text may not have the same length, `ß` becoming `SS` for instance.
+    fn convert_words(&mut self, count: usize, convert: fn(&str) -> String) {
+        let start = self.cursor();
+        let end = (0..count).fold(start, |pos, _| alnum_word_end(&self.buffer, pos));
+        let converted = convert(&self.buffer[start..end]);
+
+        self.buffer.replace_range(start..end, &converted);
+        self.set_cursor(start + converted.len());
+    }
+
+    fn insert_char(&mut self, c: char) {
+        self.buffer.insert(self.cursor(), c);
+        self.offset += 1;
+    }
+
+    // Byte index of the cursor in the buffer.
+    fn cursor(&self) -> usize {
+        byte_index(&self.buffer, self.offset)
+    }
+
+    // Puts the cursor at byte index `idx` of the buffer, which has to be a character boundary.
+    fn set_cursor(&mut self, idx: usize) {
+        self.offset = self.buffer[..idx].chars().count();
+    }
+
+    // Moves past the input, once a line has been submitted.
+    fn finish_line(&mut self, stdout: &mut impl Write, y: u16) -> io::Result<()> {
+        queue!(stdout, MoveTo(0, self.last_row(y)), MoveToNextLine(1))?;
+
+        self.inflight_buffer = None;
+        self.active_prompt = None;
+
+        writeln!(stdout)?;
+        stdout.flush()
+    }
+
+    fn validate_last_line(&self) -> Result<(), String> {
+        if let Some(validate) = self.options.line_validator.as_ref() {
+            (validate.0)(self.buffer.rsplit('\n').next().unwrap_or_default())
+        } else {
+            Ok(())
+        }
+    }
+
+    fn needs_continuation(&self) -> bool {
+        if let Some(is_complete) = self.options.is_complete.as_ref() {
+            !self.buffer.trim().is_empty() && !(is_complete.0)(&self.buffer)
+        } else {
+            false
+        }
+    }
+
+    // Runs a submitted line through history, command detection and the parser, the same way
+    // whether it was typed or queued.
+    fn interpret<F, E, C>(&mut self, line: &str, parser: &F) -> io::Result<Interpreted<C, E>>
+    where
+        F: Fn(&str) -> ParseOutcome<C, E>,
+    {
+        let raw = line;
+        let line = line.trim();
+
+        if line.is_empty() {
+            self.edits.clear();
+            return Ok(Interpreted::Skip);
+        }
+
+        let cmd_line = self.command_line(raw);
+
+        // Parsed before anything gets recorded, an incomplete command not being submitted yet.
+        let outcome = match cmd_line {
+            Some(cmd) if !cmd.is_empty() => match parser(cmd) {
+                ParseOutcome::Complete(c) => Some(Ok(c)),
+                ParseOutcome::Incomplete => return Ok(Interpreted::Incomplete),
+                ParseOutcome::Error(e) => Some(Err(e)),
+            },
+
+            _ => None,
+        };
+
+        self.edits.clear();
+
+        if self.options.ignore_space && raw.starts_with(' ') {
+            self.history.reset_cursor();
+        } else {
+            self.history.push(mask(line, &self.mask_spans(line)))?;
+        }
+
+        self.submitted = Some((raw.to_string(), cmd_line.is_some()));
+
+        match outcome {
+            Some(Ok(c)) => return Ok(Interpreted::Accepted(Input::Command(c))),
+            Some(Err(e)) => return Ok(Interpreted::Invalid(e)),
+            None if cmd_line.is_some() => return Ok(Interpreted::Skip),
+            None => {}
+        }
+
+        if self.options.exit_keywords.iter().any(|k| k == line) {
+            self.terminated = true;
+            return Ok(Interpreted::Accepted(Input::Exit));
+        }
+
+        Ok(Interpreted::Accepted(Input::String(line.to_string())))
+    }
+
+    // The command part of `raw`, without its prefix, or `None` when `raw` is a free expression.
+    fn command_line<'a>(&self, raw: &'a str) -> Option<&'a str> {
+        let line = raw.trim();
+
+        if self.options.disable_free_expression {
+            return Some(line);
+        }
+
+        let cmd_prefix = if let Some(prefix) = self.options.command_prompt.as_ref() {
+            prefix
+        } else {
+            ":"
+        };
+
+        if self.options.strict_command_prefix {
+            raw.trim_end().strip_prefix(cmd_prefix)
+        } else {
+            line.strip_prefix(cmd_prefix)
+        }
+    }
+
+    // First argument handed to clap parsers, standing for the program name.
+    fn program_name(&self) -> String {
+        if let Some(name) = self.options.clap_program_name.clone() {
+            return name;
+        }
+
+        env::current_exe()
+            .ok()
+            .and_then(|path| Some(path.file_stem()?.to_string_lossy().into_owned()))
+            .unwrap_or_default()
+    }
+
+    fn continuation_prompt(&self) -> String {
+        format!(
+            "{}{}",
+            self.options.continuation_prompt, self.options.prompt_suffix
+        )
+    }
+
+    // Cursor location as a (column, row) pair, the row being relative to the prompt row.
+    // Lines wider than the terminal wrap on several rows, which counts here.
+    fn cursor_position(&self, prompt: &str) -> (u16, u16) {
+        let width = self.terminal_width();
+        let before = &self.buffer[..self.cursor()];
+        let row = before.matches('\n').count();
+        let rows_above = self
+            .buffer
+            .split('\n')
+            .take(row)
+            .enumerate()
+            .map(|(idx, line)| self.line_cells(prompt, idx, line) / width + 1)
+            .sum::<usize>();
+        let line = before.rsplit('\n').next().unwrap_or_default();
+        let cells = self.line_cells(prompt, row, line);
+
+        ((cells % width) as u16, (rows_above + cells / width) as u16)
+    }
+
+    // Terminal cells taken by row `idx` of the input, `line`, along with what's drawn before it.
+    fn line_cells(&self, prompt: &str, idx: usize, line: &str) -> usize {
+        let prefix = if idx == 0 {
+            display_width(prompt) + display_width(self.recall_marker())
+        } else {
+            display_width(&self.continuation_prompt())
+        };
+        let gutter = display_width(&self.gutter(idx));
+        let line = if self.options.caret_notation {
+            display_width(&caret_notation(line))
+        } else {
+            display_width(line)
+        };
+
+        gutter + prefix + line
+    }
+
+    fn recall_marker(&self) -> &str {
+        if self.history.cursor() < self.history.len() {
+            &self.options.recall_marker
+        } else {
+            ""
+        }
+    }
+
+    fn terminal_width(&self) -> usize {
+        self.events
+            .size()
+            .map_or(80, |(width, _)| width.max(1) as usize)
+    }
+
+    fn terminal_height(&self) -> u16 {
+        self.events.size().map_or(24, |(_, height)| height.max(1))
+    }
+
+    // Line number shown before the prompt of row `row`, padded so the prompts of every row stay
+    // aligned, even once the input reaches ten lines or more.
+    fn gutter(&self, row: usize) -> String {
+        if !self.options.multiline_gutter {
+            return String::new();
+        }
+
+        let width = (self.buffer.matches('\n').count() + 1).to_string().len();
+
+        format!("{:>width$} ", row + 1, width = width)
+    }
+
+    fn last_row(&self, y: u16) -> u16 {
+        y + self.rendered_rows.saturating_sub(1)
+    }
+
+    fn place_cursor(&self, stdout: &mut impl Write, prompt: &str, y: u16) -> io::Result<()> {
+        let (x, row) = self.cursor_position(prompt);
+        queue!(stdout, MoveTo(x, y + row))?;
+
+        Ok(())
+    }
+
+    // Draws the prompt and the buffer, one row per line, starting at row `y`. Rows are separated
+    // with newlines so the terminal scrolls when the input grows past the bottom of the screen.
+    fn redraw(&mut self, stdout: &mut impl Write, prompt: &str, y: u16) -> io::Result<()> {
+        let continuation = self.continuation_prompt();
+        let brackets = if self.options.match_brackets && self.search.is_none() {
+            matching_bracket(&self.buffer, self.cursor())
+        } else {
+            None
+        };
+        let width = self.terminal_width();
+        let mut rows = 0;
+        let mut start = 0;
+
+        self.masked = self.mask_spans(&self.buffer);
+
+        queue!(stdout, MoveTo(0, y))?;
+
+        for (idx, line) in self.buffer.split('\n').enumerate() {
+            let prefix = if idx == 0 {
+                format!("{}{}", prompt, self.recall_marker())
+            } else {
+                write!(stdout, "\r\n")?;
+                continuation.clone()
+            };
+
+            let gutter = self.gutter(idx);
+
+            if self.options.redraw_strategy == RedrawStrategy::FullClear {
+                queue!(stdout, Clear(ClearType::CurrentLine))?;
+            }
+
+            write!(stdout, "{}{}", gutter, prefix)?;
+            self.write_line(stdout, line, start, brackets)?;
+
+            // A line filling its last row exactly leaves the cursor hanging at the end of it. The
+            // space moves it to the next row, which then belongs to the line too.
+            let cells = self.line_cells(prompt, idx, line);
+
+            if cells > 0 && cells.is_multiple_of(width) {
+                write!(stdout, " \r")?;
+            }
+
+            // Rows the line wraps on are cleared as well, whatever the strategy.
+            queue!(stdout, Clear(ClearType::UntilNewLine))?;
+
+            start += line.len() + 1;
+            rows += (cells / width + 1) as u16;
+        }
+
+        for row in rows..self.rendered_rows {
+            queue!(stdout, MoveTo(0, y + row), Clear(ClearType::CurrentLine))?;
+        }
+
+        let y = y.min(self.terminal_height().saturating_sub(rows));
+
+        self.rendered_rows = rows;
+        self.prompt_row = Some(y);
+        self.place_cursor(stdout, prompt, y)
+    }
+
+    // Writes a single line of the buffer, `start` being the offset of that line within the
+    // buffer. The highlighted part of the buffer, if any, is shown in reverse video and the matched
+    // brackets are underlined.
+    fn write_line(
+        &self,
+        stdout: &mut impl Write,
+        line: &str,
+        start: usize,
+        brackets: Option<(usize, usize)>,
+    ) -> io::Result<()> {
+        let end = start + line.len();
+        let mut written = 0;
+
+        for range in &self.highlight {
+            if range.start >= end || range.end <= start + written {
+                continue;
+            }
+
+            let from = range.start.max(start + written) - start;
+            let to = range.end.min(end) - start;
+
+            self.write_brackets(stdout, &line[written..from], start + written, brackets)?;
+            queue!(stdout, SetAttribute(Attribute::Reverse))?;
+            self.write_brackets(stdout, &line[from..to], start + from, brackets)?;
+            queue!(stdout, SetAttribute(Attribute::NoReverse))?;
+            written = to;
+        }
+
+        self.write_brackets(stdout, &line[written..], start + written, brackets)
+    }
+
+    fn leave_alternate_screen(&mut self) -> io::Result<()> {
+        if std::mem::take(&mut self.alternate_screen) {
+            queue!(self.out, LeaveAlternateScreen)?;
+            self.out.flush()?;
+        }
+
+        Ok(())
+    }
+
+    // Raw mode toggles, skipped altogether when the caller manages raw mode.
+    fn enter_raw_mode(&self) -> io::Result<()> {
+        if self.options.manage_raw_mode {
+            (self.raw_mode.enable)()?;
+        }
+
+        Ok(())
+    }
+
+    fn leave_raw_mode(&self) -> io::Result<()> {
+        if self.options.manage_raw_mode {
+            (self.raw_mode.disable)()?;
+        }
+
+        Ok(())
+    }
+
+    fn build_prompt(&self, options: &PromptOptions) -> String {
+        let base = match self.options.live_prompt.as_ref() {
+            Some((_, prompt)) => (prompt.0)(),
+            None => self.options.prompt.clone(),
+        };
+
+        let base = match self.options.vi_mode_indicators.as_ref() {
+            Some((insert, normal)) if self.options.vi_mode => match self.vi.mode() {
+                ViMode::Insert => format!("{}{}", insert, base),
+                ViMode::Normal => format!("{}{}", normal, base),
+            },
+            _ => base,
+        };
+
+        if let Some(prefix) = options.prompt.as_ref() {
+            format!("{} {}{}", prefix, base, self.options.prompt_suffix)
+        } else {
+            format!("{}{}", base, self.options.prompt_suffix)
+        }
+    }
+
+    // Leaves the line being typed on screen and draws a fresh prompt below it.
+    fn cancel_line(&mut self, stdout: &mut impl Write, prompt: &str, y: u16) -> io::Result<()> {
+        queue!(stdout, MoveTo(0, self.last_row(y)))?;
+        write!(stdout, "\r\n")?;
+
+        let y = self.row_below(self.last_row(y), 1);
+
+        self.buffer.clear();
+        self.offset = 0;
+        self.inflight_buffer = None;
+        self.rendered_rows = 0;
+
+        self.redraw(stdout, prompt, y)
+    }
+
+    // Row `rows` below `y` once moving down line by line, the terminal scrolling instead of going
+    // past its last row. Saves asking the terminal, which needs the output flushed beforehand.
+    fn row_below(&self, y: u16, rows: usize) -> u16 {
+        let bottom = self.terminal_height() - 1;
+
+        (y as usize + rows).min(bottom as usize) as u16
+    }
+
+    // Where the terminal cursor is. When the terminal can't tell, or isn't asked at all, it's
+    // assumed to be at the start of the row below the input last drawn.
+    fn position(&self) -> (u16, u16) {
+        let assumed = || match self.prompt_row {
+            Some(y) => (0, self.row_below(self.last_row(y), 1)),
+            None => self.options.assume_position.unwrap_or((0, 0)),
+        };
+
+        if self.options.assume_position.is_some() {
+            return assumed();
+        }
+
+        self.events.cursor_position().unwrap_or_else(|_| assumed())
+    }
+
+    // Whether `event` is a character simply inserted into the line, which doesn't need the line to
+    // be on screen beforehand.
+    fn inserts_plainly(&self, event: &Event) -> bool {
+        match event {
+            Event::Key(KeyEvent {
+                code: code @ KeyCode::Char(_),
+                modifiers,
+            }) => {
+                (*modifiers & !KeyModifiers::SHIFT).is_empty()
+                    && self.search.is_none()
+                    && !(self.options.vi_mode && self.vi.mode() == ViMode::Normal)
+                    && self.options.keymap.action(*code, *modifiers).is_none()
+                    && !self.is_interrupt(*code, *modifiers)
+            }
+
+            _ => false,
+        }
+    }
+
+    fn is_interrupt(&self, code: KeyCode, modifiers: KeyModifiers) -> bool {
+        let (key, required) = self.options.interrupt_key;
+
+        code == key && modifiers.contains(required)
+    }
+
+    // Writes `text`, found at `start` in the buffer, underlining the brackets it contains.
+    // Attributes take no room on screen, so the cursor column is unaffected.
+    fn write_brackets(
+        &self,
+        stdout: &mut impl Write,
+        text: &str,
+        start: usize,
+        brackets: Option<(usize, usize)>,
+    ) -> io::Result<()> {
+        let mut from = 0;
+
+        if let Some((open, close)) = brackets {
+            for pos in [open.min(close), open.max(close)] {
+                if pos < start + from || pos >= start + text.len() {
+                    continue;
+                }
+
+                let pos = pos - start;
+                self.write_text(stdout, &text[from..pos], start + from)?;
+                queue!(stdout, SetAttribute(Attribute::Underlined))?;
+                self.write_text(stdout, &text[pos..pos + 1], start + pos)?;
+                queue!(stdout, SetAttribute(Attribute::NoUnderline))?;
+                from = pos + 1;
+            }
+        }
+
+        self.write_text(stdout, &text[from..], start + from)
+    }
+
+    // Writes `text`, found at offset `start` in the buffer, with its masked characters as `*`.
+    fn write_text(&self, stdout: &mut impl Write, text: &str, start: usize) -> io::Result<()> {
+        let text = if self.masked.is_empty() {
+            text.to_string()
+        } else {
+            text.char_indices()
+                .map(|(idx, c)| {
+                    let pos = start + idx;
+
+                    // As many stars as cells once shown, so masking doesn't move the cursor. A
+                    // control character takes two in caret notation.
+                    if self.masked.iter().any(|span| span.contains(&pos)) {
+                        let cells = if self.options.caret_notation {
+                            display_width(&caret_notation(c.encode_utf8(&mut [0; 4])))
+                        } else {
+                            char_width(c)
+                        };
+
+                        "*".repeat(cells)
+                    } else {
+                        c.to_string()
+                    }
+                })
+                .collect()
+        };
+
+        let text = if self.options.caret_notation {
+            caret_notation(&text)
+        } else {
+            text
+        };
+
+        if self.options.sanitize_input {
+            write!(stdout, "{}", strip_escapes(&text))
+        } else {
+            write!(stdout, "{}", text)
+        }
+    }
+
+    fn mask_spans(&self, line: &str) -> Vec<Range<usize>> {
+        match self.options.mask_spans.as_ref() {
+            Some(spans) => (spans.0)(line),
+            None => Vec::new(),
+        }
+    }
+
+    // Prints the candidates in columns below the input, one screenful at a time when paginated,
+    // then draws the prompt and the buffer again under them.
+    fn list_completions(&mut self, candidates: &[String]) -> io::Result<()> {
+        let (width, height) = self.events.size().unwrap_or((80, 24));
+        let items = candidates.iter().map(String::as_str).collect::<Vec<_>>();
+        let rows = columns(&items, width as usize);
+        let page = if self.options.paginate_completions {
+            (height as usize).saturating_sub(1).max(1)
+        } else {
+            usize::MAX
+        };
+
+        let mut stdout = self.out.clone();
+        let events = self.events.clone();
+        let raw_mode = self.options.manage_raw_mode.then_some(self.raw_mode);
+
+        self.with_cooked_mode(move || {
+            let mut remaining = page;
+
+            for row in rows {
+                if remaining == 0 {
+                    remaining = more_prompt(&mut stdout, &events, raw_mode, page)?;
+
+                    if remaining == 0 {
+                        break;
+                    }
+                }
+
+                writeln!(stdout, "{}", row)?;
+                remaining -= 1;
+            }
+
+            stdout.flush()
+        })?
+    }
+
+    // Prints `message` below the input, then draws the prompt and the buffer again under it.
+    fn report(&mut self, message: impl Display) -> io::Result<()> {
+        let mut out = self.out.clone();
+
+        self.with_cooked_mode(move || writeln!(out, "{}", message))?
+    }
+
+    fn report_error(&mut self, error: impl Display) -> io::Result<()> {
+        match self.error_renderer.clone() {
+            Some(render) => {
+                let error = error.to_string();
+                self.with_cooked_mode(move || render(&error))
+            }
+
+            None => self.report(error),
+        }
+    }
+}
+
+impl<A> Drop for Inputs<A> {
+    fn drop(&mut self) {
+        if self.alternate_screen {
+            let _ = queue!(self.out, LeaveAlternateScreen);
+            let _ = self.out.flush();
+        }
+    }
+}
+
+// Waits for the next key and returns the character it stands for, control keys included, so it
+// can be inserted as is.
+fn read_verbatim(events: &Events) -> io::Result<Option<char>> {
+    loop {
+        if let Event::Key(KeyEvent { code, modifiers }) = events.read()? {
+            let c = match code {
+                KeyCode::Char(c) if modifiers.contains(KeyModifiers::CONTROL) => {
+                    if c.is_ascii() && ('@'..='_').contains(&c.to_ascii_uppercase()) {
+                        Some((c.to_ascii_uppercase() as u8 & 0x1f) as char)
+                    } else {
+                        None
+                    }
+                }
+                KeyCode::Char(c) => Some(c),
+                KeyCode::Tab => Some('\t'),
+                KeyCode::Enter => Some('\r'),
+                KeyCode::Esc => Some('\x1b'),
+                KeyCode::Backspace => Some('\x7f'),
+                _ => None,
+            };
+
+            return Ok(c);
+        }
+    }
+}
+
+// Shows `--More--` and waits for a key, returning how many more rows of the listing to print.
+fn more_prompt(
+    stdout: &mut impl Write,
+    events: &Events,
+    raw_mode: Option<RawMode>,
+    page: usize,
+) -> io::Result<usize> {
+    write!(stdout, "--More--")?;
+    stdout.flush()?;
+
+    if let Some(raw_mode) = raw_mode {
+        (raw_mode.enable)()?;
+    }
+
+    let key = loop {
+        if let Event::Key(key) = events.read()? {
+            break key;
+        }
+    };
+
+    if let Some(raw_mode) = raw_mode {
+        (raw_mode.disable)()?;
+    }
+
+    write!(stdout, "\r")?;
+    queue!(stdout, Clear(ClearType::CurrentLine))?;
+
+    Ok(match key.code {
+        KeyCode::Char(' ') => page,
+        KeyCode::Enter => 1,
+        _ => 0,
+    })
+}
+
+// Creates a file only the current user can access in the temporary directory. The name is never
+// reused, so a file planted there beforehand can't get written to or read back.
+fn create_scratch_file() -> io::Result<(PathBuf, fs::File)> {
+    let mut options = fs::OpenOptions::new();
+    options.write(true).create_new(true);
+
+    #[cfg(unix)]
+    std::os::unix::fs::OpenOptionsExt::mode(&mut options, 0o600);
+
+    let nanos = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |elapsed| elapsed.subsec_nanos());
+
+    for attempt in 0u32.. {
+        let name = format!("glyph-{}-{}-{}.txt", process::id(), nanos, attempt);
+        let path = env::temp_dir().join(name);
+
+        match options.open(&path) {
+            Ok(file) => return Ok((path, file)),
+            Err(e) if e.kind() == io::ErrorKind::AlreadyExists && attempt < 100 => continue,
+            Err(e) => return Err(e),
+        }
+    }
+
+    unreachable!()
+}
+
+// Byte offset `count` characters before `pos`, or the start of `text` when there aren't as many.
+// `pos` has to be on a character boundary, which the returned offset always is.
+fn chars_back(text: &str, pos: usize, count: usize) -> usize {
+    if count == 0 {
+        return pos;
+    }
+
+    text[..pos]
+        .char_indices()
+        .rev()
+        .nth(count - 1)
+        .map_or(0, |(idx, _)| idx)
+}
+
+// Byte offset `count` characters after `pos`, or the end of `text` when there aren't as many.
+fn chars_forward(text: &str, pos: usize, count: usize) -> usize {
+    text[pos..]
+        .char_indices()
+        .nth(count)
+        .map_or(text.len(), |(idx, _)| pos + idx)
+}
+
+// Byte index of the `idx`th character of `text`, or the length of `text` past its last one.
+fn byte_index(text: &str, idx: usize) -> usize {
+    text.char_indices()
+        .nth(idx)
+        .map_or(text.len(), |(pos, _)| pos)
+}
+
+// Start of the word ending at or before `pos`, words being separated by whitespace. The whole run
+// of whitespace before the word gets skipped, the start of `text` being as far as it goes.
+fn word_start(text: &str, pos: usize) -> usize {
+    separated_word_start(text, pos, char::is_whitespace)
+}
+
+// End of the word starting at or after `pos`, with the same notion of word as `word_start`. The
+// end of `text` is as far as it goes.
+fn word_end(text: &str, pos: usize) -> usize {
+    separated_word_end(text, pos, char::is_whitespace)
+}
+
+// Like `word_start`, but anything other than letters and digits separates words, so in
+// `foo/bar.baz` each of `foo`, `bar` and `baz` is a word of its own.
+fn alnum_word_start(text: &str, pos: usize) -> usize {
+    separated_word_start(text, pos, |c| !c.is_alphanumeric())
+}
+
+// Like `word_end`, with the same notion of word as `alnum_word_start`.
+fn alnum_word_end(text: &str, pos: usize) -> usize {
+    separated_word_end(text, pos, |c| !c.is_alphanumeric())
+}
+
+// Where deleting the whitespace-separated word before the character at `offset` leaves the
+// cursor, in characters. Whitespace between the word and the cursor goes as well, and only the part
+// of the word before the cursor does.
+fn big_word_start(buffer: &str, offset: usize) -> usize {
+    let start = word_start(buffer, byte_index(buffer, offset));
+
+    buffer[..start].chars().count()
+}
+
+fn separated_word_start(text: &str, pos: usize, separator: fn(char) -> bool) -> usize {
+    let mut chars = text[..pos].char_indices().rev().peekable();
+
+    while chars.next_if(|(_, c)| separator(*c)).is_some() {}
+    while chars.next_if(|(_, c)| !separator(*c)).is_some() {}
+
+    chars.peek().map_or(0, |(idx, c)| idx + c.len_utf8())
+}
+
+fn separated_word_end(text: &str, pos: usize, separator: fn(char) -> bool) -> usize {
+    let mut chars = text[pos..].char_indices().peekable();
+
+    while chars.next_if(|(_, c)| separator(*c)).is_some() {}
+    while chars.next_if(|(_, c)| !separator(*c)).is_some() {}
+
+    chars.peek().map_or(text.len(), |(idx, _)| pos + idx)
+}
+
+// `text` with every run of masked characters replaced by `***`.
+fn mask(text: &str, spans: &[Range<usize>]) -> String {
+    let mut masked = String::with_capacity(text.len());
+    let mut hiding = false;
+
+    for (idx, c) in text.char_indices() {
+        if spans.iter().any(|span| span.contains(&idx)) {
+            if !hiding {
+                masked.push_str("***");
+            }
+
+            hiding = true;
+        } else {
+            masked.push(c);
+            hiding = false;
+        }
+    }
+
+    masked
+}
+
+// Upper case for the first letter of every word, lower case for the rest.
+fn capitalize(text: &str) -> String {
+    let mut capitalized = String::with_capacity(text.len());
+    let mut in_word = false;
+
+    for c in text.chars() {
+        if !c.is_alphanumeric() {
+            capitalized.push(c);
+        } else if in_word {
+            capitalized.extend(c.to_lowercase());
+        } else {
+            capitalized.extend(c.to_uppercase());
+        }
+
+        in_word = c.is_alphanumeric();
+    }
+
+    capitalized
+}
+
+// Byte offsets of the bracket under the cursor, or right before it, and of the bracket it pairs
+// with, if any.
+fn matching_bracket(buffer: &str, cursor: usize) -> Option<(usize, usize)> {
+    const PAIRS: [(u8, u8); 3] = [(b'(', b')'), (b'[', b']'), (b'{', b'}')];
+
+    let bytes = buffer.as_bytes();
+    let is_bracket = |pos: &usize| {
+        bytes
+            .get(*pos)
+            .is_some_and(|b| PAIRS.iter().any(|(open, close)| b == open || b == close))
+    };
+
+    let pos = Some(cursor)
+        .filter(is_bracket)
+        .or_else(|| cursor.checked_sub(1).filter(is_bracket))?;
+    let bracket = bytes[pos];
+    let mut depth = 0usize;
+
+    if let Some(&(open, close)) = PAIRS.iter().find(|(open, _)| *open == bracket) {
+        for (idx, &b) in bytes.iter().enumerate().skip(pos) {
+            if b == open {
+                depth += 1;
+            } else if b == close {
+                depth -= 1;
+
+                if depth == 0 {
+                    return Some((pos, idx));
+                }
+            }
+        }
+    } else if let Some(&(open, close)) = PAIRS.iter().find(|(_, close)| *close == bracket) {
+        for idx in (0..=pos).rev() {
+            if bytes[idx] == close {
+                depth += 1;
+            } else if bytes[idx] == open {
+                depth -= 1;
+
+                if depth == 0 {
+                    return Some((pos, idx));
+                }
+            }
+        }
+    }
+
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::completion::StaticCompleter;
+    use std::sync::atomic::{AtomicBool, Ordering};
+    use std::sync::Mutex;
+
+    // Plays its events back in order, failing once they ran out.
+    struct Script(VecDeque<Event>);
+
+    impl EventSource for Script {
+        fn poll(&mut self, _: Duration) -> io::Result<bool> {
+            Ok(!self.0.is_empty())
+        }
+
+        fn read(&mut self) -> io::Result<Event> {
+            self.0
+                .pop_front()
+                .ok_or_else(|| io::Error::new(io::ErrorKind::UnexpectedEof, "no more events"))
+        }
+
+        fn size(&mut self) -> io::Result<(u16, u16)> {
+            Ok((80, 24))
+        }
+
+        fn cursor_position(&mut self) -> io::Result<(u16, u16)> {
+            Ok((0, 0))
+        }
+    }
 
-                            stdout.flush()?;
-                            continue;
-                        }
+    // Everything written to the screen, along with how many times it got flushed.
+    #[derive(Clone, Default)]
+    struct Screen(Arc<Mutex<(Vec<u8>, usize)>>);
 
-                        self.history.push(line.to_string())?;
-                        self.offset = 0;
+    impl Screen {
+        fn take(&self) -> (Vec<u8>, usize) {
+            std::mem::take(&mut *self.0.lock().unwrap())
+        }
+    }
 
-                        let cmd_line = if self.options.disable_free_expression {
-                            Some(line)
-                        } else {
-                            let cmd_prefix =
-                                if let Some(prefix) = self.options.command_prompt.as_ref() {
-                                    prefix
-                                } else {
-                                    ":"
-                                };
+    impl Write for Screen {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.0.lock().unwrap().0.extend_from_slice(buf);
+            Ok(buf.len())
+        }
 
-                            line.strip_prefix(cmd_prefix)
-                        };
+        fn flush(&mut self) -> io::Result<()> {
+            self.0.lock().unwrap().1 += 1;
+            Ok(())
+        }
+    }
 
-                        if let Some(cmd) = cmd_line {
-                            if cmd.is_empty() {
-                                writeln!(stdout)?;
-                                queue!(stdout, MoveToNextLine(1))?;
-                                write!(stdout, "{} ", prompt)?;
+    fn key(code: KeyCode) -> Event {
+        Event::Key(KeyEvent::new(code, KeyModifiers::NONE))
+    }
 
-                                stdout.flush()?;
-                                continue;
-                            }
+    fn ctrl(c: char) -> Event {
+        Event::Key(KeyEvent::new(KeyCode::Char(c), KeyModifiers::CONTROL))
+    }
 
-                            let params = cmd
-                                .split_whitespace()
-                                .map(|c| c.to_string())
-                                .collect::<Vec<_>>();
-
-                            match parser(params) {
-                                Err(e) => {
-                                    stdout.flush()?;
-                                    disable_raw_mode()?;
-                                    println!();
-                                    println!("{}", e);
-                                    enable_raw_mode()?;
-                                    queue!(stdout, MoveTo(0, y + 1))?;
-                                    write!(stdout, "{} ", prompt)?;
-                                    stdout.flush()?;
-
-                                    continue;
-                                }
+    fn alt(code: KeyCode) -> Event {
+        Event::Key(KeyEvent::new(code, KeyModifiers::ALT))
+    }
 
-                                Ok(c) => {
-                                    queue!(stdout, MoveToNextLine(1))?;
-                                    stdout.flush()?;
+    fn typed(text: &str) -> Vec<Event> {
+        text.chars().map(|c| key(KeyCode::Char(c))).collect()
+    }
 
-                                    self.inflight_buffer = None;
+    fn inputs(options: Options, events: Vec<Event>) -> (Inputs<Noop>, Screen) {
+        let screen = Screen::default();
+        let inputs = Inputs::with_io(
+            options.manage_raw_mode(false),
+            Noop,
+            Script(events.into()),
+            screen.clone(),
+        )
+        .unwrap();
 
-                                    disable_raw_mode()?;
-                                    println!();
-                                    return Ok(Some(Input::Command(c)));
-                                }
-                            }
-                        }
+        (inputs, screen)
+    }
 
-                        queue!(stdout, MoveToNextLine(1))?;
-                        stdout.flush()?;
+    // The line submitted after playing `events`, followed by Enter.
+    fn submit(options: Options, mut events: Vec<Event>) -> String {
+        events.push(key(KeyCode::Enter));
 
-                        self.inflight_buffer = None;
+        let (mut inputs, _) = inputs(options, events);
 
-                        disable_raw_mode()?;
-                        println!();
+        match inputs.next_input().unwrap() {
+            Some(Input::String(line)) => line,
+            other => panic!("expected a line, got {:?}", other),
+        }
+    }
 
-                        return Ok(Some(Input::String(line.to_string())));
-                    }
+    #[test]
+    fn submits_the_typed_line() {
+        assert_eq!(submit(Options::default(), typed("a b")), "a b");
+    }
 
-                    KeyCode::Char(c) => {
-                        self.offset += 1;
+    #[test]
+    fn backspace_removes_a_whole_multibyte_character() {
+        let line = submit(
+            Options::default(),
+            [typed("café"), vec![key(KeyCode::Backspace)]].concat(),
+        );
 
-                        if self.offset < (self.buffer.len() + 1) as u16 {
-                            self.buffer.insert((self.offset as usize) - 1, c);
-                            queue!(stdout, MoveTo(0, y), Clear(ClearType::CurrentLine))?;
-                            write!(stdout, "{} {}", prompt, self.buffer)?;
-                            queue!(stdout, MoveTo(start_pos + self.offset - 1, y))?;
-                        } else {
-                            self.buffer.push(c);
+        assert_eq!(line, "caf");
+    }
 
-                            queue!(stdout, MoveTo(0, y), Clear(ClearType::CurrentLine))?;
-                            write!(stdout, "{} {}", prompt, self.buffer,)?;
-                            queue!(stdout, MoveTo(start_pos + self.offset - 1, y),)?;
-                        }
+    #[test]
+    fn edits_in_the_middle_of_multibyte_text() {
+        let events = [
+            typed("日本語"),
+            vec![key(KeyCode::Left), key(KeyCode::Left)],
+            typed("é"),
+            vec![key(KeyCode::Right), key(KeyCode::Backspace)],
+        ]
+        .concat();
 
-                        self.inflight_buffer = Some(self.buffer.clone());
-                    }
-                    _ => {}
-                }
-            }
+        assert_eq!(submit(Options::default(), events), "日é語");
+    }
 
-            stdout.flush()?;
+    #[test]
+    fn lines_longer_than_a_u16_are_fine() {
+        // A thousand characters killed, then yanked back seventy times.
+        let events = [
+            typed(&"x".repeat(1_000)),
+            vec![ctrl('a'), ctrl('k')],
+            vec![ctrl('y'); 70],
+            vec![ctrl('a')],
+            typed("é"),
+        ]
+        .concat();
+
+        assert_eq!(
+            submit(Options::default(), events),
+            format!("é{}", "x".repeat(70_000))
+        );
+    }
+
+    // Command keeping whatever text it was parsed from.
+    #[derive(Debug)]
+    struct Raw(String);
+
+    impl FromStr for Raw {
+        type Err = String;
+
+        fn from_str(s: &str) -> Result<Self, Self::Err> {
+            Ok(Raw(s.to_string()))
+        }
+    }
+
+    #[test]
+    fn from_str_commands_get_the_line_as_typed() {
+        let options = Options::default()
+            .disable_free_expression()
+            .arg_splitter(Splitter::ShellWords);
+        let events = [
+            typed("add \"1 2\""),
+            vec![alt(KeyCode::Enter)],
+            typed("3"),
+            vec![key(KeyCode::Enter)],
+        ]
+        .concat();
+        let (mut inputs, _) = inputs(options, events);
+
+        match inputs.next_input_parsed::<Raw>().unwrap() {
+            Some(Input::Command(Raw(text))) => assert_eq!(text, "add \"1 2\"\n3"),
+            other => panic!("expected a command, got {:?}", other),
+        }
+    }
+
+    fn parse_raw(options: Options, line: &str) -> Input<Raw> {
+        let events = [typed(line), vec![key(KeyCode::Enter)]].concat();
+        let (mut inputs, _) = inputs(options, events);
+
+        inputs.next_input_parsed::<Raw>().unwrap().unwrap()
+    }
+
+    #[test]
+    fn commands_may_start_with_whitespace() {
+        let input = parse_raw(Options::default(), "  :connect");
+
+        assert!(matches!(input, Input::Command(Raw(cmd)) if cmd == "connect"));
+    }
+
+    #[test]
+    fn strict_prefix_leaves_indented_lines_to_expressions() {
+        let input = parse_raw(Options::default().strict_command_prefix(), "  :connect");
+
+        assert!(matches!(input, Input::String(line) if line == ":connect"));
+
+        let input = parse_raw(Options::default().strict_command_prefix(), ":connect");
+
+        assert!(matches!(input, Input::Command(Raw(cmd)) if cmd == "connect"));
+    }
+
+    fn cycling() -> Options {
+        Options::default()
+            .completer(StaticCompleter::new(["cat", "car", "cab"]))
+            .completion_style(CompletionStyle::Cycle)
+    }
+
+    #[test]
+    fn tab_cycles_through_candidates_and_wraps_around() {
+        let tabs = |n| vec![key(KeyCode::Tab); n];
+
+        assert_eq!(submit(cycling(), [typed("ca"), tabs(1)].concat()), "cat");
+        assert_eq!(submit(cycling(), [typed("ca"), tabs(3)].concat()), "cab");
+        assert_eq!(submit(cycling(), [typed("ca"), tabs(4)].concat()), "cat");
+    }
+
+    #[test]
+    fn shift_tab_cycles_backward() {
+        let events = [typed("ca"), vec![key(KeyCode::BackTab)]].concat();
+        assert_eq!(submit(cycling(), events), "cab");
+
+        let events = [typed("ca"), vec![key(KeyCode::BackTab); 4]].concat();
+        assert_eq!(submit(cycling(), events), "cab");
+    }
+
+    #[test]
+    fn tab_completes_a_single_candidate_for_good() {
+        let events = [typed("cat"), vec![key(KeyCode::Tab); 2]].concat();
+        assert_eq!(submit(cycling(), events), "cat");
+    }
+
+    #[test]
+    fn tab_first_completes_the_common_prefix() {
+        let events = [typed("x c"), vec![key(KeyCode::Tab)]].concat();
+        assert_eq!(submit(cycling(), events), "x ca");
+
+        let events = [typed("x c"), vec![key(KeyCode::Tab); 2]].concat();
+        assert_eq!(submit(cycling(), events), "x cat");
+    }
+
+    #[test]
+    fn inputs_can_move_to_another_thread() {
+        fn assert_send<T: Send>() {}
+
+        assert_send::<Inputs<Noop>>();
+        assert_send::<Inputs<FileBackend>>();
+    }
+
+    #[test]
+    fn with_io_reads_the_injected_events() {
+        let (mut inputs, screen) = inputs(
+            Options::default(),
+            [typed("hello"), vec![key(KeyCode::Enter)]].concat(),
+        );
+
+        assert!(matches!(
+            inputs.next_input().unwrap(),
+            Some(Input::String(line)) if line == "hello"
+        ));
+
+        let (written, _) = screen.take();
+        assert!(String::from_utf8_lossy(&written).contains("hello"));
+    }
+
+    #[test]
+    fn recalls_multiline_entries_with_the_cursor_at_the_end() {
+        let events = [
+            vec![key(KeyCode::Up), key(KeyCode::Left)],
+            typed("X"),
+            vec![key(KeyCode::Enter)],
+        ]
+        .concat();
+        let (mut inputs, _) = inputs(Options::default(), events);
+
+        inputs.preload_history(["first\nsecond"]).unwrap();
+
+        assert!(matches!(
+            inputs.next_input().unwrap(),
+            Some(Input::String(line)) if line == "first\nseconXd"
+        ));
+    }
+
+    #[test]
+    fn scratch_files_are_private_and_never_reused() {
+        let (first, _) = create_scratch_file().unwrap();
+        let (second, _) = create_scratch_file().unwrap();
+
+        assert_ne!(first, second);
+
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+
+            let mode = fs::metadata(&first).unwrap().permissions().mode();
+            assert_eq!(mode & 0o777, 0o600);
+        }
+
+        fs::remove_file(first).unwrap();
+        fs::remove_file(second).unwrap();
+    }
+
+    #[test]
+    fn overwrite_mode_types_over_the_line() {
+        let events = [
+            typed("abcd"),
+            vec![ctrl('a'), key(KeyCode::Insert)],
+            typed("XY"),
+        ]
+        .concat();
+        assert_eq!(submit(Options::default(), events), "XYcd");
+
+        // Past the end of the line, characters get appended.
+        let events = [
+            typed("ab"),
+            vec![ctrl('a'), key(KeyCode::Insert)],
+            typed("WXYZ"),
+        ]
+        .concat();
+        assert_eq!(submit(Options::default(), events), "WXYZ");
+
+        let events = [
+            typed("ab"),
+            vec![ctrl('a'), key(KeyCode::Insert), key(KeyCode::Insert)],
+            typed("X"),
+        ]
+        .concat();
+        assert_eq!(submit(Options::default(), events), "Xab");
+    }
+
+    #[test]
+    fn deleting_before_the_start_of_the_line_does_nothing() {
+        let (mut inputs, _) = inputs(Options::default(), Vec::new());
+        inputs.buffer = "abc".to_string();
+        inputs.offset = 0;
+
+        inputs.delete_before_cursor(1);
+
+        assert_eq!(inputs.buffer, "abc");
+        assert_eq!(inputs.offset, 0);
+
+        let events = [
+            typed("ab"),
+            vec![ctrl('a'), key(KeyCode::Backspace), ctrl('h')],
+        ]
+        .concat();
+        assert_eq!(submit(Options::default(), events), "ab");
+    }
+
+    #[test]
+    fn case_commands_convert_the_next_word() {
+        let events = [
+            typed("hello world"),
+            vec![ctrl('a'), alt(KeyCode::Char('u'))],
+        ]
+        .concat();
+        assert_eq!(submit(Options::default(), events), "HELLO world");
+
+        let events = [
+            typed("HELLO WORLD"),
+            vec![ctrl('a'), alt(KeyCode::Char('l')), alt(KeyCode::Char('c'))],
+        ]
+        .concat();
+        assert_eq!(submit(Options::default(), events), "hello World");
+    }
+
+    #[test]
+    fn case_commands_handle_non_ascii_words() {
+        let events = [
+            typed("éclair straße"),
+            vec![ctrl('a'), alt(KeyCode::Char('c')), alt(KeyCode::Char('u'))],
+            typed("!"),
+        ]
+        .concat();
+
+        assert_eq!(submit(Options::default(), events), "Éclair STRASSE!");
+    }
+
+    #[test]
+    fn a_single_ctrl_c_exits_by_default() {
+        let (mut inputs, _) = inputs(Options::default(), [typed("abc"), vec![ctrl('c')]].concat());
+
+        assert!(matches!(inputs.next_input().unwrap(), Some(Input::Exit)));
+    }
+
+    #[test]
+    fn double_ctrl_c_cancels_the_line_then_exits() {
+        let options = Options::default().double_ctrl_c_exits();
+        let (mut inputs, _) = inputs(options, [typed("abc"), vec![ctrl('c'), ctrl('c')]].concat());
+
+        assert!(matches!(inputs.next_input().unwrap(), Some(Input::Exit)));
+    }
+
+    #[test]
+    fn typing_between_two_ctrl_c_starts_over() {
+        let options = Options::default().double_ctrl_c_exits();
+        let events = [vec![ctrl('c')], typed("x"), vec![ctrl('c')], typed("y")].concat();
+
+        assert_eq!(submit(options, events), "y");
+    }
+
+    #[test]
+    fn up_leaves_the_line_alone_without_history_navigation() {
+        let events = [typed("new"), vec![key(KeyCode::Up), key(KeyCode::Enter)]].concat();
+        let (mut inputs, _) = inputs(Options::default(), events);
+        let options = PromptOptions::default().no_history_nav();
+
+        inputs.preload_history(["old"]).unwrap();
+
+        assert!(matches!(
+            inputs.next_input_with_options(&options).unwrap(),
+            Some(Input::String(line)) if line == "new"
+        ));
+    }
+
+    #[test]
+    fn pasted_escape_sequences_are_only_kept_out_of_the_display() {
+        for sanitize in [false, true] {
+            let options = if sanitize {
+                Options::default().sanitize_input()
+            } else {
+                Options::default()
+            };
+            let events = [typed("\x1b[31mred"), vec![key(KeyCode::Enter)]].concat();
+            let (mut inputs, screen) = inputs(options, events);
+
+            assert!(matches!(
+                inputs.next_input().unwrap(),
+                Some(Input::String(line)) if line == "\x1b[31mred"
+            ));
+
+            let (written, _) = screen.take();
+            let written = String::from_utf8_lossy(&written);
+            assert_eq!(written.contains("\x1b[31m"), !sanitize, "{:?}", written);
+            assert!(written.contains("red"));
+        }
+    }
+
+    // The line submitted after preloading `history` and playing `events`, followed by Enter.
+    fn submit_with_history(history: &[&str], mut events: Vec<Event>) -> String {
+        events.push(key(KeyCode::Enter));
+
+        let (mut inputs, _) = inputs(Options::default(), events);
+        inputs.preload_history(history.iter().copied()).unwrap();
+
+        match inputs.next_input().unwrap() {
+            Some(Input::String(line)) => line,
+            other => panic!("expected a line, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn reverse_search_goes_through_older_matches_and_esc_gives_up() {
+        let history = ["git status", "ls", "git commit"];
+
+        let events = [typed("draft"), vec![ctrl('r')], typed("git")].concat();
+        assert_eq!(submit_with_history(&history, events), "git commit");
+
+        let events = [vec![ctrl('r')], typed("git"), vec![ctrl('r')]].concat();
+        assert_eq!(submit_with_history(&history, events), "git status");
+
+        let events = [
+            typed("draft"),
+            vec![ctrl('r')],
+            typed("git"),
+            vec![ctrl('r'), key(KeyCode::Esc)],
+        ]
+        .concat();
+        assert_eq!(submit_with_history(&history, events), "draft");
+    }
+
+    #[test]
+    fn typing_refines_the_current_match() {
+        let history = ["ab old", "a", "ab new"];
+
+        let events = [vec![ctrl('r')], typed("a"), vec![ctrl('r')], typed("b")].concat();
+        assert_eq!(submit_with_history(&history, events), "ab old");
+
+        // Erasing the character gets back to the match found before typing it.
+        let events = [
+            vec![ctrl('r')],
+            typed("a"),
+            vec![ctrl('r')],
+            typed("b"),
+            vec![key(KeyCode::Backspace)],
+        ]
+        .concat();
+        assert_eq!(submit_with_history(&history, events), "a");
+    }
+
+    #[test]
+    fn ctrl_c_cancels_the_search() {
+        let events = [
+            typed("draft"),
+            vec![ctrl('r')],
+            typed("git"),
+            vec![ctrl('c')],
+        ]
+        .concat();
+
+        assert_eq!(submit_with_history(&["git status"], events), "draft");
+    }
+
+    #[test]
+    fn other_keys_accept_the_match_and_do_what_they_usually_do() {
+        let events = [
+            vec![ctrl('r')],
+            typed("stat"),
+            vec![ctrl('e'), key(KeyCode::Left)],
+            typed("X"),
+        ]
+        .concat();
+
+        assert_eq!(submit_with_history(&["git status"], events), "git statuXs");
+    }
+
+    #[test]
+    fn quoted_insert_takes_the_next_key_literally() {
+        let events = [
+            typed("a"),
+            vec![ctrl('v'), ctrl('c')],
+            typed("b"),
+            vec![key(KeyCode::Enter)],
+        ]
+        .concat();
+        let (mut inputs, screen) = inputs(Options::default().caret_notation(), events);
+
+        assert!(matches!(
+            inputs.next_input().unwrap(),
+            Some(Input::String(line)) if line == "a\x03b"
+        ));
+
+        let (written, _) = screen.take();
+        assert!(String::from_utf8_lossy(&written).contains("a^Cb"));
+    }
+
+    #[test]
+    fn quoted_insert_of_tab_does_not_complete() {
+        let options = || Options::default().completer(StaticCompleter::new(["abc"]));
+
+        let events = [typed("a"), vec![ctrl('v'), key(KeyCode::Tab)], typed("b")].concat();
+        assert_eq!(submit(options(), events), "a\tb");
+
+        let events = [typed("a"), vec![key(KeyCode::Tab)], typed("b")].concat();
+        assert_eq!(submit(options(), events), "abcb");
+    }
+
+    #[test]
+    fn the_history_cursor_follows_navigation() {
+        // The script runs out after the two Up presses, leaving the second entry recalled.
+        let (mut inputs, _) = inputs(Options::default(), vec![key(KeyCode::Up); 2]);
+
+        inputs.preload_history(["a", "b", "c"]).unwrap();
+
+        assert_eq!(inputs.history_len(), 3);
+        assert_eq!(inputs.history_cursor(), 3);
+        assert!(inputs.next_input().is_err());
+        assert_eq!(inputs.history_cursor(), 1);
+
+        inputs.reset_history_cursor();
+
+        assert_eq!(inputs.history_cursor(), 3);
+        assert_eq!(inputs.history_len(), 3);
+    }
+
+    fn command_args(options: Options, line: &str) -> Vec<String> {
+        let events = [typed(line), vec![key(KeyCode::Enter)]].concat();
+        let (mut inputs, _) = inputs(options, events);
+
+        match inputs.next_input().unwrap() {
+            Some(Input::Command(params)) => params.values(),
+            other => panic!("expected a command, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn the_splitter_decides_how_commands_get_tokenized() {
+        let line = r#":add "a b" c\ d"#;
+
+        assert_eq!(
+            command_args(Options::default(), line),
+            ["add", "\"a", "b\"", "c\\", "d"]
+        );
+        assert_eq!(
+            command_args(Options::default().arg_splitter(Splitter::ShellWords), line),
+            ["add", "a b", "c d"]
+        );
+
+        let options = Options::default().arg_splitter(Splitter::custom(|line| {
+            line.split(',').map(str::to_string).collect()
+        }));
+        assert_eq!(command_args(options, ":a,b c"), ["a", "b c"]);
+    }
+
+    #[test]
+    fn lines_starting_with_a_space_stay_out_of_the_history() {
+        let events = [typed(" secret"), vec![key(KeyCode::Enter)]].concat();
+        let (mut ignoring, _) = inputs(Options::default().ignore_space(), events);
+
+        ignoring.next_input().unwrap();
+
+        assert_eq!(ignoring.recent(1).count(), 0);
+
+        let events = [typed(" kept"), vec![key(KeyCode::Enter)]].concat();
+        let (mut keeping, _) = inputs(Options::default(), events);
+
+        keeping.next_input().unwrap();
+
+        assert_eq!(keeping.recent(1).collect::<Vec<_>>(), ["kept"]);
+    }
+
+    // Bytes written and flushes made by a session playing `events`, followed by Enter.
+    fn output(options: Options, mut events: Vec<Event>) -> (usize, usize) {
+        events.push(key(KeyCode::Enter));
+
+        let (mut inputs, screen) = inputs(options, events);
+        inputs.next_input().unwrap();
+
+        let (written, flushes) = screen.take();
+        (written.len(), flushes)
+    }
+
+    #[test]
+    fn every_event_is_flushed_exactly_once() {
+        let options = || Options::default().double_ctrl_c_exits();
+        let (_, baseline) = output(options(), typed("x"));
+
+        // Cancelling the line and submitting an empty one both draw a fresh prompt.
+        let before = [
+            typed("ab"),
+            vec![key(KeyCode::Left), ctrl('c'), key(KeyCode::Enter)],
+        ]
+        .concat();
+        let (_, flushes) = output(options(), [before.clone(), typed("x")].concat());
+
+        assert_eq!(flushes - baseline, before.len());
+    }
+
+    #[test]
+    fn typing_at_the_end_of_the_line_writes_the_character_alone() {
+        let (short, _) = output(Options::default(), typed(&"x".repeat(10)));
+        let (long, _) = output(Options::default(), typed(&"x".repeat(20)));
+
+        assert_eq!(long - short, 10);
+    }
+
+    #[test]
+    fn word_boundaries_skip_runs_of_whitespace() {
+        let text = "cd   foo/bar.baz  ";
+
+        assert_eq!(word_start(text, text.len()), 5);
+        assert_eq!(word_start(text, 5), 0);
+        assert_eq!(word_start(text, 4), 0);
+        assert_eq!(word_end(text, 0), 2);
+        assert_eq!(word_end(text, 2), 16);
+        assert_eq!(word_end(text, 8), 16);
+    }
+
+    #[test]
+    fn word_boundaries_clamp_to_the_text() {
+        assert_eq!(word_start("  cd", 2), 0);
+        assert_eq!(word_start("cd", 0), 0);
+        assert_eq!(word_end("cd  ", 2), 4);
+        assert_eq!(word_end("cd", 2), 2);
+        assert_eq!(word_end("", 0), 0);
+    }
+
+    #[test]
+    fn alt_left_jumps_over_punctuated_words() {
+        let events = [
+            typed("cd foo/bar.baz"),
+            vec![alt(KeyCode::Left)],
+            typed("X"),
+        ]
+        .concat();
+        assert_eq!(submit(Options::default(), events), "cd Xfoo/bar.baz");
+
+        let events = [
+            typed("cd   foo/bar.baz"),
+            vec![ctrl('a'), alt(KeyCode::Right)],
+            typed("X"),
+        ]
+        .concat();
+        assert_eq!(submit(Options::default(), events), "cdX   foo/bar.baz");
+    }
+
+    #[test]
+    fn deleting_a_word_backward_from_its_end() {
+        assert_eq!(big_word_start("cd foo/bar.baz", 14), 3);
+        assert_eq!(big_word_start("cd", 2), 0);
+        assert_eq!(big_word_start("", 0), 0);
+    }
+
+    #[test]
+    fn deleting_a_word_backward_from_its_middle() {
+        assert_eq!(big_word_start("cd foobar", 6), 3);
+        assert_eq!(big_word_start("cd café crème", 7), 3);
+
+        let events = [
+            typed("cd foobar"),
+            vec![key(KeyCode::Left); 3],
+            vec![ctrl('w')],
+        ]
+        .concat();
+        assert_eq!(submit(Options::default(), events), "cd bar");
+    }
+
+    #[test]
+    fn deleting_a_word_backward_takes_the_whitespace_after_it() {
+        assert_eq!(big_word_start("cd foo   ", 9), 3);
+        assert_eq!(big_word_start("cd foo   bar", 9), 3);
+        assert_eq!(big_word_start("   ", 3), 0);
+
+        let events = [
+            typed("cd foo   bar"),
+            vec![key(KeyCode::Left); 3],
+            vec![ctrl('w')],
+        ]
+        .concat();
+        assert_eq!(submit(Options::default(), events), "cd bar");
+    }
+
+    #[test]
+    fn ctrl_w_kills_back_to_the_previous_whitespace() {
+        let events = [typed("cd foo/bar.baz"), vec![ctrl('w')], typed("X")].concat();
+        assert_eq!(submit(Options::default(), events), "cd X");
+
+        let events = [
+            typed("cd foo/bar.baz  "),
+            vec![ctrl('w'), ctrl('y'), ctrl('y')],
+        ]
+        .concat();
+        assert_eq!(
+            submit(Options::default(), events),
+            "cd foo/bar.baz  foo/bar.baz"
+        );
+    }
+
+    #[test]
+    fn alphanumeric_words_stop_at_punctuation() {
+        let text = "cd foo/bar.baz";
+
+        assert_eq!(alnum_word_start(text, 14), 11);
+        assert_eq!(alnum_word_start(text, 11), 7);
+        assert_eq!(alnum_word_start(text, 7), 3);
+        assert_eq!(alnum_word_start(text, 3), 0);
+        assert_eq!(alnum_word_end(text, 3), 6);
+        assert_eq!(alnum_word_end(text, 6), 10);
+        assert_eq!(alnum_word_end(text, 10), 14);
+    }
+
+    #[test]
+    fn alt_backspace_and_ctrl_w_delete_different_words() {
+        let alt_backspace = alt(KeyCode::Backspace);
+        let line = |keys: Vec<Event>| {
+            let events = [typed("cd foo/bar.baz"), keys, typed("X")].concat();
+            submit(Options::default(), events)
+        };
+
+        assert_eq!(line(vec![alt_backspace]), "cd foo/bar.X");
+        assert_eq!(line(vec![alt_backspace; 2]), "cd foo/X");
+        assert_eq!(line(vec![alt_backspace; 3]), "cd X");
+        assert_eq!(line(vec![ctrl('w')]), "cd X");
+        assert_eq!(line(vec![ctrl('w'); 2]), "X");
+    }
+
+    #[test]
+    fn masked_control_characters_take_as_many_cells_as_shown() {
+        let options = Options::default()
+            .caret_notation()
+            .mask_spans(|line: &str| std::iter::once(0..line.len()).collect());
+        let (mut inputs, screen) = inputs(options, Vec::new());
+        inputs.buffer = "a\x01".to_string();
+        inputs.masked = inputs.mask_spans(&inputs.buffer);
+
+        inputs.write_text(&mut screen.clone(), "a\x01", 0).unwrap();
+
+        let (written, _) = screen.take();
+        assert_eq!(written, b"***");
+        assert_eq!(inputs.line_cells("", 0, &inputs.buffer), 3);
+    }
+
+    #[cfg(feature = "fuzzy")]
+    #[test]
+    fn fuzzy_completion_matches_more_than_prefixes() {
+        let options = |mode| {
+            Options::default()
+                .completer(StaticCompleter::new([
+                    "grep",
+                    "git checkout origin",
+                    "git status",
+                ]))
+                .search_mode(mode)
+        };
+        let events = [typed("gco"), vec![key(KeyCode::Tab)]].concat();
+
+        assert_eq!(
+            submit(options(SearchMode::Fuzzy), events.clone()),
+            "git checkout origin"
+        );
+        assert_eq!(submit(options(SearchMode::Exact), events), "gco");
+    }
+
+    // Blows up on the first key it's asked for.
+    struct Exploding;
+
+    impl EventSource for Exploding {
+        fn poll(&mut self, _: Duration) -> io::Result<bool> {
+            Ok(true)
+        }
+
+        fn read(&mut self) -> io::Result<Event> {
+            panic!("forced panic")
+        }
+
+        fn size(&mut self) -> io::Result<(u16, u16)> {
+            Ok((80, 24))
+        }
+
+        fn cursor_position(&mut self) -> io::Result<(u16, u16)> {
+            Ok((0, 0))
         }
     }
+
+    #[test]
+    fn a_panic_while_reading_restores_the_terminal() {
+        static RAW: AtomicBool = AtomicBool::new(false);
+        static ENTERED: AtomicBool = AtomicBool::new(false);
+
+        fn enable() -> io::Result<()> {
+            RAW.store(true, Ordering::SeqCst);
+            ENTERED.store(true, Ordering::SeqCst);
+            Ok(())
+        }
+
+        fn disable() -> io::Result<()> {
+            RAW.store(false, Ordering::SeqCst);
+            Ok(())
+        }
+
+        let screen = Screen::default();
+        let options = Options::default().alternate_screen();
+        let mut inputs = Inputs::with_io(options, Noop, Exploding, screen.clone()).unwrap();
+        inputs.raw_mode = RawMode { enable, disable };
+
+        let result = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
+            let _ = inputs.next_input();
+        }));
+
+        assert!(result.is_err());
+        assert!(ENTERED.load(Ordering::SeqCst), "raw mode entered");
+        assert!(!RAW.load(Ordering::SeqCst), "raw mode left");
+
+        let (written, _) = screen.take();
+        let written = String::from_utf8_lossy(&written);
+        assert!(written.contains("\x1b[?25h"), "cursor shown");
+        assert!(written.ends_with("\x1b[?1049l"), "alternate screen left");
+    }
+
+    // Can't tell the size of the terminal.
+    struct Sizeless(Script);
+
+    impl EventSource for Sizeless {
+        fn poll(&mut self, timeout: Duration) -> io::Result<bool> {
+            self.0.poll(timeout)
+        }
+
+        fn read(&mut self) -> io::Result<Event> {
+            self.0.read()
+        }
+
+        fn size(&mut self) -> io::Result<(u16, u16)> {
+            Err(io::Error::other("no size"))
+        }
+
+        fn cursor_position(&mut self) -> io::Result<(u16, u16)> {
+            Ok((0, 0))
+        }
+    }
+
+    #[test]
+    fn an_unknown_terminal_size_is_no_error() {
+        let events = [typed("abc"), vec![key(KeyCode::Enter)]].concat();
+        let source = Sizeless(Script(events.into()));
+        let options = Options::default().manage_raw_mode(false);
+        let mut inputs = Inputs::with_io(options, Noop, source, Screen::default()).unwrap();
+
+        assert!(matches!(
+            inputs.next_input().unwrap(),
+            Some(Input::String(line)) if line == "abc"
+        ));
+    }
+
+    #[test]
+    fn wide_characters_take_two_cells_each() {
+        let (mut inputs, _) = inputs(Options::default(), Vec::new());
+        inputs.buffer = "你好".to_string();
+        inputs.offset = 2;
+
+        assert_eq!(inputs.cursor_position("> "), (6, 0));
+
+        inputs.offset = 1;
+        assert_eq!(inputs.cursor_position("> "), (4, 0));
+    }
+
+    #[test]
+    fn vi_mode_edits_from_normal_mode() {
+        let options = Options::default().vi_mode();
+        let events = [typed("hello world"), vec![key(KeyCode::Esc)], typed("bix")].concat();
+
+        assert_eq!(submit(options, events), "hello xworld");
+
+        let options = Options::default().vi_mode();
+        let events = [typed("hello"), vec![key(KeyCode::Esc)], typed("ddiyo")].concat();
+
+        assert_eq!(submit(options, events), "yo");
+    }
 }
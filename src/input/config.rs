@@ -0,0 +1,411 @@
+use super::split::Splitter;
+use crate::{
+    CompletionStyle, EscAction, HistoryErrorPolicy, Options, PositionQuery, RedrawStrategy,
+    SearchMode, TabBehavior, UiStream,
+};
+use serde::Deserialize;
+
+/// Declarative counterpart of the `Options` builder, meant to be read from an application config
+/// file. Every field is optional, anything left out keeps the `Options` default. Unknown fields are
+/// rejected, and enum values are spelled in snake case, `full_clear` or `shell_words` for instance.
+#[derive(Debug, Default, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct OptionsConfig {
+    pub prompt: Option<String>,
+    pub prompt_suffix: Option<String>,
+    pub header: Option<String>,
+    pub author: Option<String>,
+    pub version: Option<String>,
+    pub date: Option<String>,
+    pub command_prompt: Option<String>,
+    pub tips: Vec<String>,
+    pub random_tips: bool,
+    pub disable_free_expression: bool,
+    pub strict_command_prefix: bool,
+    pub compact_prompt: bool,
+    pub keep_cursor_column: bool,
+    pub return_parse_errors: bool,
+    pub search_prompt: Option<String>,
+    pub exit_keywords: Vec<String>,
+    pub redraw_strategy: Option<RedrawStrategy>,
+    pub on_history_error: Option<HistoryErrorPolicy>,
+    pub completion_style: Option<CompletionStyle>,
+    pub paginate_completions: bool,
+    pub match_brackets: bool,
+    pub history_wrap: bool,
+    pub sanitize_input: bool,
+    pub esc_action: Option<EscAction>,
+    pub manage_raw_mode: Option<bool>,
+    pub alternate_screen: bool,
+    pub history_limit: Option<usize>,
+    pub multiline_gutter: bool,
+    pub ui_stream: Option<UiStream>,
+    pub double_ctrl_c_exits: bool,
+    pub assume_position: Option<(u16, u16)>,
+    pub clap_program_name: Option<String>,
+    pub caret_notation: bool,
+    pub transient_prompt: Option<String>,
+    pub position_query: Option<PositionQuery>,
+    pub tab_behavior: Option<TabBehavior>,
+    pub recall_marker: Option<String>,
+    pub search_mode: Option<SearchMode>,
+    pub arg_splitter: Option<Splitter>,
+    pub script_comments: Option<String>,
+    pub exit_after_script: bool,
+    pub ignore_space: bool,
+    pub ignore_dups: bool,
+    pub vi_mode: bool,
+    pub vi_mode_indicators: Option<(String, String)>,
+}
+
+impl From<OptionsConfig> for Options {
+    fn from(config: OptionsConfig) -> Self {
+        let mut options = Options::default();
+
+        if let Some(prompt) = config.prompt {
+            options = options.prompt(prompt);
+        }
+
+        if let Some(suffix) = config.prompt_suffix {
+            options = options.prompt_suffix(suffix);
+        }
+
+        if let Some(header) = config.header {
+            options = options.header(header);
+        }
+
+        if let Some(author) = config.author {
+            options = options.author(author);
+        }
+
+        if let Some(version) = config.version {
+            options = options.version(version);
+        }
+
+        if let Some(date) = config.date {
+            options = options.date(date);
+        }
+
+        if let Some(prompt) = config.command_prompt {
+            options = options.command_prompt(prompt);
+        }
+
+        if !config.tips.is_empty() {
+            options = options.tips(config.tips);
+        }
+
+        if config.random_tips {
+            options = options.random_tips();
+        }
+
+        if config.disable_free_expression {
+            options = options.disable_free_expression();
+        }
+
+        if config.strict_command_prefix {
+            options = options.strict_command_prefix();
+        }
+
+        if config.compact_prompt {
+            options = options.compact_prompt();
+        }
+
+        if config.keep_cursor_column {
+            options = options.keep_cursor_column();
+        }
+
+        if config.return_parse_errors {
+            options = options.return_parse_errors();
+        }
+
+        if !config.exit_keywords.is_empty() {
+            options = options.exit_keywords(config.exit_keywords);
+        }
+
+        if let Some(prompt) = config.search_prompt {
+            options = options.search_prompt(prompt);
+        }
+
+        if let Some(strategy) = config.redraw_strategy {
+            options = options.redraw_strategy(strategy);
+        }
+
+        if let Some(policy) = config.on_history_error {
+            options = options.on_history_error(policy);
+        }
+
+        if let Some(style) = config.completion_style {
+            options = options.completion_style(style);
+        }
+
+        if config.paginate_completions {
+            options = options.paginate_completions();
+        }
+
+        if config.match_brackets {
+            options = options.match_brackets();
+        }
+
+        if config.history_wrap {
+            options = options.history_wrap(true);
+        }
+
+        if config.sanitize_input {
+            options = options.sanitize_input();
+        }
+
+        if let Some(action) = config.esc_action {
+            options = options.esc_action(action);
+        }
+
+        if let Some(manage) = config.manage_raw_mode {
+            options = options.manage_raw_mode(manage);
+        }
+
+        if config.alternate_screen {
+            options = options.alternate_screen();
+        }
+
+        if let Some(limit) = config.history_limit {
+            options = options.history_limit(limit);
+        }
+
+        if config.multiline_gutter {
+            options = options.multiline_gutter(true);
+        }
+
+        if let Some(stream) = config.ui_stream {
+            options = options.ui_stream(stream);
+        }
+
+        if config.double_ctrl_c_exits {
+            options = options.double_ctrl_c_exits();
+        }
+
+        if let Some((column, row)) = config.assume_position {
+            options = options.assume_position(column, row);
+        }
+
+        if let Some(name) = config.clap_program_name {
+            options = options.clap_program_name(name);
+        }
+
+        if config.caret_notation {
+            options = options.caret_notation();
+        }
+
+        if let Some(prompt) = config.transient_prompt {
+            options = options.transient_prompt(prompt);
+        }
+
+        if let Some(query) = config.position_query {
+            options = options.position_query(query);
+        }
+
+        if let Some(behavior) = config.tab_behavior {
+            options = options.tab_behavior(behavior);
+        }
+
+        if let Some(marker) = config.recall_marker {
+            options = options.recall_marker(marker);
+        }
+
+        if let Some(mode) = config.search_mode {
+            options = options.search_mode(mode);
+        }
+
+        if let Some(splitter) = config.arg_splitter {
+            options = options.arg_splitter(splitter);
+        }
+
+        if let Some(prefix) = config.script_comments {
+            options = options.script_comments(prefix);
+        }
+
+        if config.exit_after_script {
+            options = options.exit_after_script();
+        }
+
+        if config.ignore_space {
+            options = options.ignore_space();
+        }
+
+        if config.ignore_dups {
+            options = options.ignore_dups();
+        }
+
+        if config.vi_mode {
+            options = options.vi_mode();
+        }
+
+        if let Some((insert, normal)) = config.vi_mode_indicators {
+            options = options.vi_mode_indicators(insert, normal);
+        }
+
+        options
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn options(json: &str) -> Options {
+        serde_json::from_str::<OptionsConfig>(json).unwrap().into()
+    }
+
+    #[test]
+    fn every_field_makes_it_to_the_options() {
+        let options = options(
+            r#"{
+                "prompt": "sql",
+                "prompt_suffix": "> ",
+                "header": "Welcome",
+                "author": "me",
+                "version": "1.0",
+                "date": "today",
+                "command_prompt": "cmd",
+                "tips": ["first", "second"],
+                "random_tips": true,
+                "disable_free_expression": true,
+                "strict_command_prefix": true,
+                "compact_prompt": true,
+                "keep_cursor_column": true,
+                "return_parse_errors": true,
+                "search_prompt": "search:",
+                "exit_keywords": ["quit", "exit"],
+                "redraw_strategy": "overwrite",
+                "on_history_error": "warn",
+                "completion_style": "cycle",
+                "paginate_completions": true,
+                "match_brackets": true,
+                "history_wrap": true,
+                "sanitize_input": true,
+                "esc_action": "clear_line",
+                "manage_raw_mode": false,
+                "alternate_screen": true,
+                "history_limit": 100,
+                "multiline_gutter": true,
+                "ui_stream": "stderr",
+                "double_ctrl_c_exits": true,
+                "assume_position": [3, 4],
+                "clap_program_name": "app",
+                "caret_notation": true,
+                "transient_prompt": "$",
+                "position_query": "ui_stream",
+                "tab_behavior": "insert",
+                "recall_marker": "*",
+                "search_mode": "exact",
+                "arg_splitter": "shell_words",
+                "script_comments": "--",
+                "exit_after_script": true,
+                "ignore_space": true,
+                "ignore_dups": true,
+                "vi_mode": true,
+                "vi_mode_indicators": ["I", "N"]
+            }"#,
+        );
+
+        assert_eq!(options.prompt, "sql");
+        assert_eq!(options.prompt_suffix, "> ");
+        assert_eq!(options.header, "Welcome");
+        assert_eq!(options.author, "me");
+        assert_eq!(options.version, "1.0");
+        assert_eq!(options.date, "today");
+        assert_eq!(options.command_prompt.as_deref(), Some("cmd"));
+        assert_eq!(options.tips, ["first", "second"]);
+        assert!(options.random_tips);
+        assert!(options.disable_free_expression);
+        assert!(options.strict_command_prefix);
+        assert!(options.compact_prompt);
+        assert!(options.keep_cursor_column);
+        assert!(options.return_parse_errors);
+        assert_eq!(options.search_prompt, "search:");
+        assert_eq!(options.exit_keywords, ["quit", "exit"]);
+        assert_eq!(options.redraw_strategy, RedrawStrategy::Overwrite);
+        assert_eq!(options.on_history_error, HistoryErrorPolicy::Warn);
+        assert_eq!(options.completion_style, CompletionStyle::Cycle);
+        assert!(options.paginate_completions);
+        assert!(options.match_brackets);
+        assert!(options.history_wrap);
+        assert!(options.sanitize_input);
+        assert_eq!(options.esc_action, EscAction::ClearLine);
+        assert!(!options.manage_raw_mode);
+        assert!(options.alternate_screen);
+        assert_eq!(options.history_limit, Some(100));
+        assert!(options.multiline_gutter);
+        assert_eq!(options.ui_stream, UiStream::Stderr);
+        assert!(options.double_ctrl_c_exits);
+        assert_eq!(options.assume_position, Some((3, 4)));
+        assert_eq!(options.clap_program_name.as_deref(), Some("app"));
+        assert!(options.caret_notation);
+        assert_eq!(options.transient_prompt.as_deref(), Some("$"));
+        assert_eq!(options.position_query, PositionQuery::UiStream);
+        assert_eq!(options.tab_behavior, Some(TabBehavior::Insert));
+        assert_eq!(options.recall_marker, "*");
+        assert_eq!(options.search_mode, SearchMode::Exact);
+        assert!(matches!(options.arg_splitter, Splitter::ShellWords));
+        assert_eq!(options.script_comments.as_deref(), Some("--"));
+        assert!(options.exit_after_script);
+        assert!(options.ignore_space);
+        assert!(options.ignore_dups);
+        assert!(options.vi_mode);
+        assert_eq!(
+            options.vi_mode_indicators,
+            Some(("I".to_string(), "N".to_string()))
+        );
+    }
+
+    #[test]
+    fn an_empty_config_keeps_the_defaults() {
+        let options = options("{}");
+        let defaults = Options::default();
+
+        assert_eq!(options.prompt, defaults.prompt);
+        assert_eq!(options.prompt_suffix, defaults.prompt_suffix);
+        assert_eq!(options.search_prompt, defaults.search_prompt);
+        assert!(options.manage_raw_mode);
+        assert_eq!(options.history_limit, None);
+        assert_eq!(options.tab_behavior, None);
+        assert!(matches!(options.arg_splitter, Splitter::Whitespace));
+    }
+
+    #[test]
+    fn unknown_fields_are_rejected() {
+        let error = serde_json::from_str::<OptionsConfig>(r#"{"prompt": "a", "colour": true}"#)
+            .unwrap_err();
+
+        assert!(
+            error.to_string().contains("unknown field `colour`"),
+            "{}",
+            error
+        );
+    }
+
+    #[test]
+    fn enum_values_are_spelled_in_snake_case() {
+        let cases = [
+            (r#"{"redraw_strategy": "full_clear"}"#, true),
+            (r#"{"redraw_strategy": "FullClear"}"#, false),
+            (r#"{"esc_action": "cancel"}"#, true),
+            (r#"{"esc_action": "clear-line"}"#, false),
+            (r#"{"on_history_error": "fail"}"#, true),
+            (r#"{"completion_style": "menu"}"#, true),
+            (r#"{"ui_stream": "stdout"}"#, true),
+            (r#"{"position_query": "tty"}"#, true),
+            (r#"{"position_query": "UiStream"}"#, false),
+            (r#"{"tab_behavior": "complete"}"#, true),
+            (r#"{"arg_splitter": "whitespace"}"#, true),
+            (r#"{"arg_splitter": "custom"}"#, false),
+        ];
+
+        for (json, valid) in cases {
+            assert_eq!(
+                serde_json::from_str::<OptionsConfig>(json).is_ok(),
+                valid,
+                "{}",
+                json
+            );
+        }
+    }
+}
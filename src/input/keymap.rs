@@ -0,0 +1,371 @@
+use crossterm::event::{KeyCode, KeyEvent, KeyModifiers};
+use std::fmt::{self, Display};
+
+/// What a key bound in a `Keymap` does.
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub enum Action {
+    /// Inserts the character at the cursor, over the one under it in overwrite mode. Characters
+    /// typed without a binding of their own do this.
+    InsertChar(char),
+    /// Inserts `text` at the cursor. The cursor then lands `cursor_at` characters into the
+    /// snippet, or right after it when `None`.
+    InsertSnippet {
+        text: String,
+        cursor_at: Option<usize>,
+    },
+    /// Inserts the next key as is, control characters included.
+    InsertVerbatim,
+    /// Inserts a line break instead of submitting.
+    InsertNewline,
+    /// Inserts the last word of the previous entry. Repeated, it takes the entry before instead.
+    InsertLastArg,
+    MoveHome,
+    MoveEnd,
+    MoveLeft,
+    MoveRight,
+    MoveWordLeft,
+    MoveWordRight,
+    /// Deletes the character before the cursor.
+    Backspace,
+    /// Deletes the character under the cursor.
+    Delete,
+    /// Deletes back to the start of the word, anything but letters and digits separating words.
+    DeleteWordBackward,
+    /// Deletes back to the previous whitespace, `foo/bar.baz` going in one go.
+    DeleteBigWordBackward,
+    DeleteWordForward,
+    /// Deletes up to the end of the line, or the line break when already there.
+    KillToEnd,
+    KillToStart,
+    /// Inserts the text deleted last.
+    Yank,
+    /// Right after a yank, swaps the yanked text for the text deleted before it.
+    YankPop,
+    HistoryPrevious,
+    HistoryNext,
+    /// Starts a reverse incremental search through the history. Typing refines the match, Ctrl-R
+    /// goes on with older entries, and Esc or the interrupt key give up. Any other key keeps the
+    /// match and then does what it usually does.
+    ReverseSearch,
+    Submit,
+    /// Inserts spaces, completes or does nothing, as `Options::tab_behavior` says.
+    Tab,
+    Complete,
+    CompleteBackward,
+    /// Does what `Options::esc_action` says.
+    Escape,
+    ClearLine,
+    /// Leaves the line on screen and starts over with a fresh prompt below it.
+    CancelLine,
+    /// Does what the interrupt key does.
+    Interrupt,
+    UppercaseWord,
+    LowercaseWord,
+    CapitalizeWord,
+    ToggleOverwrite,
+    /// Opens the line in `$VISUAL` or `$EDITOR`.
+    EditExternally,
+    /// Waits for Ctrl-E to open the line in an editor, the way Ctrl-X Ctrl-E does.
+    EditorPrefix,
+}
+
+impl Action {
+    /// Short description of what the action does, as listed by `Inputs::key_bindings`.
+    pub fn describe(&self) -> String {
+        let description = match self {
+            Action::InsertChar(c) => return format!("insert {:?}", c),
+            Action::InsertSnippet { text, .. } => return format!("insert {:?}", text),
+            Action::InsertVerbatim => "insert the next key verbatim",
+            Action::InsertNewline => "insert a newline",
+            Action::InsertLastArg => "insert the last word of the previous entry",
+            Action::MoveHome => "move to the start of the line",
+            Action::MoveEnd => "move to the end of the line",
+            Action::MoveLeft => "move one character left",
+            Action::MoveRight => "move one character right",
+            Action::MoveWordLeft => "move to the start of the word",
+            Action::MoveWordRight => "move to the end of the word",
+            Action::Backspace => "delete the character before the cursor",
+            Action::Delete => "delete the character under the cursor",
+            Action::DeleteWordBackward => "delete the word before the cursor",
+            Action::DeleteBigWordBackward => "delete back to the previous whitespace",
+            Action::DeleteWordForward => "delete the word after the cursor",
+            Action::KillToEnd => "delete up to the end of the line",
+            Action::KillToStart => "delete up to the start of the line",
+            Action::Yank => "insert the text deleted last",
+            Action::YankPop => "after a yank, insert the text deleted before instead",
+            Action::HistoryPrevious => "recall the previous history entry",
+            Action::HistoryNext => "recall the next history entry",
+            Action::ReverseSearch => "search the history backward",
+            Action::Submit => "submit the line",
+            Action::Tab => "insert spaces or complete, depending on the options",
+            Action::Complete => "complete the word under the cursor",
+            Action::CompleteBackward => "cycle completions backward",
+            Action::Escape => "clear or cancel the line, depending on the options",
+            Action::ClearLine => "clear the line",
+            Action::CancelLine => "cancel the line",
+            Action::Interrupt => "exit",
+            Action::UppercaseWord => "uppercase the next word",
+            Action::LowercaseWord => "lowercase the next word",
+            Action::CapitalizeWord => "capitalize the next word",
+            Action::ToggleOverwrite => "toggle overwrite mode",
+            Action::EditExternally => "edit the line in $VISUAL or $EDITOR",
+            Action::EditorPrefix => "followed by Ctrl-E, edit the line in $VISUAL or $EDITOR",
+        };
+
+        description.to_string()
+    }
+}
+
+/// A key along with the modifiers held, shown the way help screens write it, `Ctrl-R` or `Alt-.`
+/// for instance.
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Hash)]
+pub struct KeyCombo {
+    pub code: KeyCode,
+    pub modifiers: KeyModifiers,
+}
+
+impl KeyCombo {
+    pub fn new(code: KeyCode, modifiers: KeyModifiers) -> Self {
+        Self { code, modifiers }
+    }
+}
+
+impl From<KeyEvent> for KeyCombo {
+    fn from(key: KeyEvent) -> Self {
+        Self::new(key.code, key.modifiers)
+    }
+}
+
+impl Display for KeyCombo {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (modifier, name) in [
+            (KeyModifiers::CONTROL, "Ctrl-"),
+            (KeyModifiers::ALT, "Alt-"),
+            (KeyModifiers::SHIFT, "Shift-"),
+        ] {
+            if self.modifiers.contains(modifier) {
+                f.write_str(name)?;
+            }
+        }
+
+        match self.code {
+            KeyCode::Char(' ') => f.write_str("Space"),
+            KeyCode::Char(c) if self.modifiers.contains(KeyModifiers::CONTROL) => {
+                write!(f, "{}", c.to_ascii_uppercase())
+            }
+            KeyCode::Char(c) => write!(f, "{}", c),
+            KeyCode::F(n) => write!(f, "F{}", n),
+            KeyCode::BackTab => f.write_str("Shift-Tab"),
+            KeyCode::PageUp => f.write_str("PageUp"),
+            KeyCode::PageDown => f.write_str("PageDown"),
+            code => write!(f, "{:?}", code),
+        }
+    }
+}
+
+/// Keys bound to actions. Characters typed without a binding of their own are inserted, and the
+/// interrupt key, unless bound, does what `Options::interrupt_key` says.
+#[derive(Debug, Default, Clone)]
+pub struct Keymap {
+    bindings: Vec<(KeyEvent, Action)>,
+}
+
+impl Keymap {
+    /// A keymap without any binding, to build one from scratch.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// The bindings glyph uses unless told otherwise, the emacs ones readline users know. Start
+    /// from it to add or change a few bindings.
+    pub fn emacs_default() -> Self {
+        let ctrl = |c| KeyEvent::new(KeyCode::Char(c), KeyModifiers::CONTROL);
+        let alt = |code| KeyEvent::new(code, KeyModifiers::ALT);
+        let key = |code| KeyEvent::new(code, KeyModifiers::NONE);
+
+        let bindings = vec![
+            (ctrl('r'), Action::ReverseSearch),
+            (ctrl('x'), Action::EditorPrefix),
+            (ctrl('a'), Action::MoveHome),
+            (ctrl('e'), Action::MoveEnd),
+            (ctrl('b'), Action::MoveLeft),
+            (ctrl('f'), Action::MoveRight),
+            (key(KeyCode::Left), Action::MoveLeft),
+            (key(KeyCode::Right), Action::MoveRight),
+            (alt(KeyCode::Left), Action::MoveWordLeft),
+            (alt(KeyCode::Right), Action::MoveWordRight),
+            (
+                KeyEvent::new(KeyCode::Left, KeyModifiers::CONTROL),
+                Action::MoveWordLeft,
+            ),
+            (
+                KeyEvent::new(KeyCode::Right, KeyModifiers::CONTROL),
+                Action::MoveWordRight,
+            ),
+            (key(KeyCode::Up), Action::HistoryPrevious),
+            (key(KeyCode::Down), Action::HistoryNext),
+            (key(KeyCode::Backspace), Action::Backspace),
+            // Some terminals send Ctrl-H for Backspace.
+            (ctrl('h'), Action::Backspace),
+            (alt(KeyCode::Backspace), Action::DeleteWordBackward),
+            (ctrl('w'), Action::DeleteBigWordBackward),
+            (alt(KeyCode::Char('d')), Action::DeleteWordForward),
+            (ctrl('k'), Action::KillToEnd),
+            (ctrl('u'), Action::KillToStart),
+            (ctrl('y'), Action::Yank),
+            (alt(KeyCode::Char('y')), Action::YankPop),
+            (key(KeyCode::Enter), Action::Submit),
+            (alt(KeyCode::Enter), Action::InsertNewline),
+            (key(KeyCode::Tab), Action::Tab),
+            (key(KeyCode::BackTab), Action::CompleteBackward),
+            (ctrl('q'), Action::InsertVerbatim),
+            (ctrl('v'), Action::InsertVerbatim),
+            (key(KeyCode::Esc), Action::Escape),
+            (alt(KeyCode::Char('.')), Action::InsertLastArg),
+            (alt(KeyCode::Char('u')), Action::UppercaseWord),
+            (alt(KeyCode::Char('l')), Action::LowercaseWord),
+            (alt(KeyCode::Char('c')), Action::CapitalizeWord),
+            (key(KeyCode::Insert), Action::ToggleOverwrite),
+        ];
+
+        Self { bindings }
+    }
+
+    /// Binds `key` to `action`, replacing whatever `key` was bound to.
+    pub fn bind(mut self, key: KeyEvent, action: Action) -> Self {
+        self.bindings.retain(|(bound, _)| *bound != key);
+        self.bindings.push((key, action));
+        self
+    }
+
+    /// Removes the binding of `key`, if any. Unbound characters get inserted like any other.
+    pub fn unbind(mut self, key: KeyEvent) -> Self {
+        self.bindings.retain(|(bound, _)| *bound != key);
+        self
+    }
+
+    /// Action bound to `code` with `modifiers`. Shift doesn't matter for keys bound without it.
+    pub fn action(&self, code: KeyCode, modifiers: KeyModifiers) -> Option<&Action> {
+        let find = |modifiers| {
+            self.bindings
+                .iter()
+                .find(|(key, _)| key.code == code && key.modifiers == modifiers)
+                .map(|(_, action)| action)
+        };
+
+        find(modifiers).or_else(|| find(modifiers - KeyModifiers::SHIFT))
+    }
+
+    pub fn bindings(&self) -> impl Iterator<Item = (KeyCombo, &Action)> {
+        self.bindings
+            .iter()
+            .map(|(key, action)| (KeyCombo::from(*key), action))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ctrl(c: char) -> KeyEvent {
+        KeyEvent::new(KeyCode::Char(c), KeyModifiers::CONTROL)
+    }
+
+    #[test]
+    fn binding_a_key_replaces_its_default_action() {
+        let default = Keymap::emacs_default();
+        let keymap = Keymap::emacs_default().bind(ctrl('a'), Action::MoveEnd);
+
+        assert_eq!(
+            default.action(KeyCode::Char('a'), KeyModifiers::CONTROL),
+            Some(&Action::MoveHome)
+        );
+        assert_eq!(
+            keymap.action(KeyCode::Char('a'), KeyModifiers::CONTROL),
+            Some(&Action::MoveEnd)
+        );
+        assert_eq!(keymap.bindings().count(), default.bindings().count());
+    }
+
+    #[test]
+    fn unbound_keys_have_no_action() {
+        let keymap = Keymap::emacs_default().unbind(ctrl('w'));
+
+        assert_eq!(
+            keymap.action(KeyCode::Char('w'), KeyModifiers::CONTROL),
+            None
+        );
+        assert_eq!(
+            keymap.action(KeyCode::Char('u'), KeyModifiers::CONTROL),
+            Some(&Action::KillToStart)
+        );
+        assert_eq!(
+            Keymap::new().action(KeyCode::Enter, KeyModifiers::NONE),
+            None
+        );
+    }
+
+    #[test]
+    fn shift_falls_back_to_the_binding_without_it() {
+        let keymap = Keymap::emacs_default();
+
+        assert_eq!(
+            keymap.action(KeyCode::Left, KeyModifiers::SHIFT),
+            Some(&Action::MoveLeft)
+        );
+        assert_eq!(
+            keymap.action(
+                KeyCode::Char('r'),
+                KeyModifiers::CONTROL | KeyModifiers::SHIFT
+            ),
+            Some(&Action::ReverseSearch)
+        );
+
+        // A binding of its own wins over the fallback, which only ever drops Shift.
+        let keymap = keymap.bind(
+            KeyEvent::new(KeyCode::Left, KeyModifiers::SHIFT),
+            Action::MoveHome,
+        );
+
+        assert_eq!(
+            keymap.action(KeyCode::Left, KeyModifiers::SHIFT),
+            Some(&Action::MoveHome)
+        );
+        assert_eq!(
+            keymap.action(KeyCode::Left, KeyModifiers::NONE),
+            Some(&Action::MoveLeft)
+        );
+        assert_eq!(
+            keymap.action(KeyCode::Left, KeyModifiers::CONTROL | KeyModifiers::ALT),
+            None
+        );
+    }
+
+    #[test]
+    fn key_combos_show_the_way_help_screens_write_them() {
+        let shown = |code, modifiers| KeyCombo::new(code, modifiers).to_string();
+
+        assert_eq!(shown(KeyCode::Char('r'), KeyModifiers::CONTROL), "Ctrl-R");
+        assert_eq!(shown(KeyCode::Char('.'), KeyModifiers::ALT), "Alt-.");
+        assert_eq!(
+            shown(
+                KeyCode::Char('x'),
+                KeyModifiers::CONTROL | KeyModifiers::ALT
+            ),
+            "Ctrl-Alt-X"
+        );
+        assert_eq!(
+            shown(KeyCode::Backspace, KeyModifiers::ALT),
+            "Alt-Backspace"
+        );
+        assert_eq!(
+            shown(KeyCode::Char(' '), KeyModifiers::CONTROL),
+            "Ctrl-Space"
+        );
+        assert_eq!(shown(KeyCode::BackTab, KeyModifiers::NONE), "Shift-Tab");
+        assert_eq!(shown(KeyCode::F(5), KeyModifiers::NONE), "F5");
+        assert_eq!(shown(KeyCode::PageDown, KeyModifiers::NONE), "PageDown");
+        assert_eq!(shown(KeyCode::Left, KeyModifiers::SHIFT), "Shift-Left");
+        assert_eq!(shown(KeyCode::Char('a'), KeyModifiers::NONE), "a");
+    }
+}
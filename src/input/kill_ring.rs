@@ -0,0 +1,116 @@
+// How many kills are kept, the oldest ones being dropped first.
+const KILL_RING_SIZE: usize = 16;
+
+// Text removed by the kill commands, for yanking it back later. Successive kills accumulate into
+// a single entry, the way readline does it.
+#[derive(Debug, Default)]
+pub struct KillRing {
+    entries: Vec<String>,
+    // Entry yanked last, counted from the most recent one.
+    yanked: usize,
+}
+
+impl KillRing {
+    // Records `text` as killed. With `accumulate`, it joins the most recent entry instead, in
+    // front of it when it was killed backward, from before the cursor.
+    pub fn kill(&mut self, text: &str, backward: bool, accumulate: bool) {
+        self.yanked = 0;
+
+        match self.entries.last_mut() {
+            Some(last) if accumulate && backward => last.insert_str(0, text),
+            Some(last) if accumulate => last.push_str(text),
+            _ => {
+                if self.entries.len() == KILL_RING_SIZE {
+                    self.entries.remove(0);
+                }
+
+                self.entries.push(text.to_string());
+            }
+        }
+    }
+
+    // Most recent kill.
+    pub fn yank(&mut self) -> Option<&str> {
+        self.yanked = 0;
+        self.entries.last().map(String::as_str)
+    }
+
+    // Kill older than the one yanked last, going back to the most recent one past the oldest.
+    pub fn rotate(&mut self) -> Option<&str> {
+        if self.entries.is_empty() {
+            return None;
+        }
+
+        self.yanked = (self.yanked + 1) % self.entries.len();
+
+        let idx = self.entries.len() - 1 - self.yanked;
+        Some(self.entries[idx].as_str())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn yanks_the_most_recent_kill() {
+        let mut ring = KillRing::default();
+        assert_eq!(ring.yank(), None);
+
+        ring.kill("foo", false, false);
+        ring.kill("bar", false, false);
+
+        assert_eq!(ring.yank(), Some("bar"));
+    }
+
+    #[test]
+    fn kills_in_a_row_accumulate_in_reading_order() {
+        let mut ring = KillRing::default();
+
+        ring.kill("b", false, false);
+        ring.kill("c", false, true);
+        ring.kill("a", true, true);
+
+        assert_eq!(ring.yank(), Some("abc"));
+        assert_eq!(ring.rotate(), Some("abc"));
+    }
+
+    #[test]
+    fn rotating_goes_through_older_kills_and_wraps_around() {
+        let mut ring = KillRing::default();
+        assert_eq!(ring.rotate(), None);
+
+        for text in ["one", "two", "three"] {
+            ring.kill(text, false, false);
+        }
+
+        assert_eq!(ring.yank(), Some("three"));
+        assert_eq!(ring.rotate(), Some("two"));
+        assert_eq!(ring.rotate(), Some("one"));
+        assert_eq!(ring.rotate(), Some("three"));
+
+        // A new kill or a fresh yank starts over from the most recent one.
+        ring.rotate();
+        assert_eq!(ring.yank(), Some("three"));
+        assert_eq!(ring.rotate(), Some("two"));
+    }
+
+    #[test]
+    fn the_oldest_kill_goes_once_the_ring_is_full() {
+        let mut ring = KillRing::default();
+
+        for n in 0..=KILL_RING_SIZE {
+            ring.kill(&n.to_string(), false, false);
+        }
+
+        let newest = KILL_RING_SIZE.to_string();
+        assert_eq!(ring.yank(), Some(newest.as_str()));
+
+        // Every kill but the first one is still there, the ring then wrapping around.
+        for n in (1..KILL_RING_SIZE).rev() {
+            assert_eq!(ring.rotate(), Some(n.to_string().as_str()));
+        }
+
+        assert_eq!(ring.rotate(), Some(newest.as_str()));
+    }
+}
@@ -0,0 +1,132 @@
+use crate::history::History;
+use crate::persistence::Persistence;
+use crate::SearchMode;
+use std::iter;
+use std::ops::Range;
+
+// State of an ongoing reverse incremental search, along with what the line looked like before
+// the search started.
+pub struct Search {
+    query: String,
+    saved_buffer: String,
+    saved_offset: usize,
+    // Index of the history entry matched last.
+    current: Option<usize>,
+    // What `current` was before each character of the query got typed, so erasing it gets back to
+    // the match it had.
+    previous: Vec<Option<usize>>,
+}
+
+impl Search {
+    pub fn new(buffer: String, offset: usize) -> Self {
+        Self {
+            query: String::new(),
+            saved_buffer: buffer,
+            saved_offset: offset,
+            current: None,
+            previous: Vec::new(),
+        }
+    }
+
+    pub fn query(&self) -> &str {
+        &self.query
+    }
+
+    pub fn push(&mut self, c: char) {
+        self.previous.push(self.current);
+        self.query.push(c);
+    }
+
+    pub fn pop(&mut self) {
+        if self.query.pop().is_some() {
+            self.current = self.previous.pop().flatten();
+        }
+    }
+
+    pub fn saved(&self) -> (&str, usize) {
+        (&self.saved_buffer, self.saved_offset)
+    }
+
+    // Entry matching the query best, along with the byte ranges of the line the query matched.
+    // With an exact search that's the most recent entry containing the query. A fuzzy search
+    // scores every entry instead, the most recent one winning ties. The search starts from the
+    // current match, so a longer query refines it the way readline does. With `older`, only the
+    // entries older than the current match are considered, and the current match stays when
+    // none of them matches.
+    pub fn find<A: Persistence>(
+        &mut self,
+        history: &History<A>,
+        mode: SearchMode,
+        older: bool,
+    ) -> Option<(String, Vec<Range<usize>>)> {
+        if self.query.is_empty() {
+            self.current = None;
+            return None;
+        }
+
+        let from = match self.current {
+            Some(current) if older => current,
+            Some(current) => current + 1,
+            None => history.entries().len(),
+        };
+
+        let (idx, line, ranges) = match mode {
+            SearchMode::Exact => {
+                let (idx, line) = history.search_backward(&self.query, from)?;
+                let start = line.find(&self.query).unwrap_or_default();
+                let range = start..start + self.query.len();
+
+                (idx, line, iter::once(range).collect())
+            }
+
+            #[cfg(feature = "fuzzy")]
+            SearchMode::Fuzzy => history
+                .entries()
+                .iter()
+                .enumerate()
+                .take(from)
+                .rev()
+                .filter_map(|(idx, entry)| {
+                    fuzzy_match(&self.query, &entry.line)
+                        .map(|(score, ranges)| (score, idx, entry.line.clone(), ranges))
+                })
+                .fold(
+                    None,
+                    |best: Option<(i64, usize, String, _)>, found| match best {
+                        Some(best) if best.0 >= found.0 => Some(best),
+                        _ => Some(found),
+                    },
+                )
+                .map(|(_, idx, line, ranges)| (idx, line, ranges))?,
+        };
+
+        self.current = Some(idx);
+
+        Some((line, ranges))
+    }
+}
+
+// Scores how well `pattern` matches `text`, its characters appearing in order but not necessarily
+// next to each other. The matched characters are returned as byte ranges of `text`, adjacent ones
+// being merged.
+#[cfg(feature = "fuzzy")]
+pub(crate) fn fuzzy_match(pattern: &str, text: &str) -> Option<(i64, Vec<Range<usize>>)> {
+    use fuzzy_matcher::skim::SkimMatcherV2;
+    use fuzzy_matcher::FuzzyMatcher;
+
+    let (score, indices) = SkimMatcherV2::default().fuzzy_indices(text, pattern)?;
+    let mut ranges: Vec<Range<usize>> = Vec::new();
+
+    for (idx, (start, c)) in text.char_indices().enumerate() {
+        if indices.binary_search(&idx).is_err() {
+            continue;
+        }
+
+        match ranges.last_mut() {
+            Some(range) if range.end == start => range.end += c.len_utf8(),
+            _ => ranges.push(start..start + c.len_utf8()),
+        }
+    }
+
+    Some((score, ranges))
+}
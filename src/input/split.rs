@@ -0,0 +1,104 @@
+use std::fmt;
+use std::sync::Arc;
+
+type Lexer = dyn Fn(&str) -> Vec<String> + Send + Sync;
+
+/// How a command line is cut into the arguments handed to the parser.
+#[derive(Clone, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(rename_all = "snake_case"))]
+pub enum Splitter {
+    /// Splits on whitespace, quotes and backslashes being regular characters.
+    #[default]
+    Whitespace,
+    /// Splits the way a POSIX shell would: quoted text stays in one argument, `'...'` keeping it
+    /// as is, `"..."` and unquoted text honoring backslash escapes.
+    ShellWords,
+    /// Leaves it to a lexer of your own.
+    #[cfg_attr(feature = "serde", serde(skip))]
+    Custom(Arc<Lexer>),
+}
+
+impl Splitter {
+    pub fn custom(split: impl Fn(&str) -> Vec<String> + Send + Sync + 'static) -> Self {
+        Splitter::Custom(Arc::new(split))
+    }
+
+    pub fn split(&self, line: &str) -> Vec<String> {
+        match self {
+            Splitter::Whitespace => line.split_whitespace().map(|w| w.to_string()).collect(),
+            Splitter::ShellWords => shell_words(line),
+            Splitter::Custom(split) => split(line),
+        }
+    }
+}
+
+impl fmt::Debug for Splitter {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Splitter::Whitespace => f.write_str("Whitespace"),
+            Splitter::ShellWords => f.write_str("ShellWords"),
+            Splitter::Custom(_) => f.write_str("Custom(<hook>)"),
+        }
+    }
+}
+
+// An unterminated quote runs to the end of the line and a trailing backslash is kept, a line being
+// split as best it can rather than rejected.
+fn shell_words(line: &str) -> Vec<String> {
+    let mut words = Vec::new();
+    let mut word = String::new();
+    // Whether a word got started, `''` being an empty argument rather than nothing.
+    let mut started = false;
+    let mut chars = line.chars();
+
+    while let Some(c) = chars.next() {
+        match c {
+            c if c.is_whitespace() => {
+                if std::mem::take(&mut started) {
+                    words.push(std::mem::take(&mut word));
+                }
+            }
+
+            '\'' => {
+                started = true;
+                word.extend(chars.by_ref().take_while(|c| *c != '\''));
+            }
+
+            '"' => {
+                started = true;
+
+                while let Some(c) = chars.next() {
+                    match c {
+                        '"' => break,
+                        '\\' => match chars.next() {
+                            Some(c @ ('"' | '\\' | '$' | '`')) => word.push(c),
+                            Some(c) => {
+                                word.push('\\');
+                                word.push(c);
+                            }
+                            None => word.push('\\'),
+                        },
+                        c => word.push(c),
+                    }
+                }
+            }
+
+            '\\' => {
+                started = true;
+                word.push(chars.next().unwrap_or('\\'));
+            }
+
+            c => {
+                started = true;
+                word.push(c);
+            }
+        }
+    }
+
+    if started {
+        words.push(word);
+    }
+
+    words
+}
@@ -0,0 +1,208 @@
+use super::keymap::Action;
+use crossterm::event::{KeyCode, KeyModifiers};
+
+#[derive(Debug, Default, Clone, Copy, Eq, PartialEq)]
+pub enum ViMode {
+    #[default]
+    Insert,
+    Normal,
+}
+
+// What a key does in vi mode.
+#[derive(Debug, Eq, PartialEq)]
+pub enum ViKey {
+    // Handled the usual way, through the keymap.
+    Keymap,
+    Perform(Action),
+    // Consumed, at most changing the mode.
+    Ignore,
+}
+
+// Modal state of the vi mode. Every prompt starts in insert mode, where keys behave as usual
+// until Escape switches to normal mode and its commands.
+#[derive(Debug, Default)]
+pub struct ViState {
+    mode: ViMode,
+    // First key of a two keys command, like the first `d` of `dd`.
+    pending: Option<char>,
+}
+
+impl ViState {
+    pub fn mode(&self) -> ViMode {
+        self.mode
+    }
+
+    pub fn reset(&mut self) {
+        *self = Self::default();
+    }
+
+    pub fn key(&mut self, code: KeyCode, modifiers: KeyModifiers) -> ViKey {
+        if self.mode == ViMode::Insert {
+            if code == KeyCode::Esc && modifiers.is_empty() {
+                self.mode = ViMode::Normal;
+
+                // Like vi, the cursor goes back onto the last character typed.
+                return ViKey::Perform(Action::MoveLeft);
+            }
+
+            return ViKey::Keymap;
+        }
+
+        // Control keys, arrows and Enter keep working in normal mode.
+        if modifiers.intersects(KeyModifiers::CONTROL | KeyModifiers::ALT) {
+            return ViKey::Keymap;
+        }
+
+        let c = match code {
+            KeyCode::Char(c) => c,
+            KeyCode::Esc => {
+                self.pending = None;
+                return ViKey::Ignore;
+            }
+            _ => return ViKey::Keymap,
+        };
+
+        if let Some(pending) = self.pending.take() {
+            return match (pending, c) {
+                ('d', 'd') => ViKey::Perform(Action::ClearLine),
+                _ => ViKey::Ignore,
+            };
+        }
+
+        let action = match c {
+            'h' => Action::MoveLeft,
+            'l' => Action::MoveRight,
+            'w' => Action::MoveWordRight,
+            'b' => Action::MoveWordLeft,
+            '0' | '^' => Action::MoveHome,
+            '$' => Action::MoveEnd,
+            'k' => Action::HistoryPrevious,
+            'j' => Action::HistoryNext,
+            'x' => Action::Delete,
+
+            'i' | 'a' | 'I' | 'A' => {
+                self.mode = ViMode::Insert;
+
+                match c {
+                    'a' => Action::MoveRight,
+                    'I' => Action::MoveHome,
+                    'A' => Action::MoveEnd,
+                    _ => return ViKey::Ignore,
+                }
+            }
+
+            'd' => {
+                self.pending = Some(c);
+                return ViKey::Ignore;
+            }
+
+            _ => return ViKey::Ignore,
+        };
+
+        ViKey::Perform(action)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crossterm::event::KeyEvent;
+
+    fn key(c: char) -> KeyEvent {
+        KeyEvent::new(KeyCode::Char(c), KeyModifiers::NONE)
+    }
+
+    fn esc() -> KeyEvent {
+        KeyEvent::new(KeyCode::Esc, KeyModifiers::NONE)
+    }
+
+    // What each of `keys` does, along with the mode it leaves the state in.
+    fn play(state: &mut ViState, keys: &[KeyEvent]) -> Vec<(ViKey, ViMode)> {
+        keys.iter()
+            .map(|key| (state.key(key.code, key.modifiers), state.mode()))
+            .collect()
+    }
+
+    #[test]
+    fn starts_in_insert_mode_and_escape_enters_normal_mode() {
+        let mut state = ViState::default();
+
+        assert_eq!(
+            play(&mut state, &[key('h'), esc(), esc()]),
+            [
+                (ViKey::Keymap, ViMode::Insert),
+                (ViKey::Perform(Action::MoveLeft), ViMode::Normal),
+                (ViKey::Ignore, ViMode::Normal),
+            ]
+        );
+    }
+
+    #[test]
+    fn normal_mode_keys_move_the_cursor() {
+        let mut state = ViState::default();
+        state.key(KeyCode::Esc, KeyModifiers::NONE);
+
+        let actions = play(
+            &mut state,
+            &[key('h'), key('l'), key('w'), key('b'), key('q')],
+        )
+        .into_iter()
+        .map(|(key, _)| key)
+        .collect::<Vec<_>>();
+
+        assert_eq!(
+            actions,
+            [
+                ViKey::Perform(Action::MoveLeft),
+                ViKey::Perform(Action::MoveRight),
+                ViKey::Perform(Action::MoveWordRight),
+                ViKey::Perform(Action::MoveWordLeft),
+                ViKey::Ignore,
+            ]
+        );
+        assert_eq!(state.mode(), ViMode::Normal);
+    }
+
+    #[test]
+    fn i_and_a_go_back_to_insert_mode() {
+        let mut state = ViState::default();
+
+        assert_eq!(
+            play(&mut state, &[esc(), key('i')])[1],
+            (ViKey::Ignore, ViMode::Insert)
+        );
+        assert_eq!(
+            play(&mut state, &[esc(), key('a')])[1],
+            (ViKey::Perform(Action::MoveRight), ViMode::Insert)
+        );
+    }
+
+    #[test]
+    fn dd_clears_the_line_and_anything_else_cancels_it() {
+        let mut state = ViState::default();
+        state.key(KeyCode::Esc, KeyModifiers::NONE);
+
+        assert_eq!(
+            play(&mut state, &[key('d'), key('d')])[1].0,
+            ViKey::Perform(Action::ClearLine)
+        );
+        assert_eq!(play(&mut state, &[key('d'), key('w')])[1].0, ViKey::Ignore);
+        assert_eq!(
+            play(&mut state, &[key('d'), esc(), key('h')])[2].0,
+            ViKey::Perform(Action::MoveLeft)
+        );
+    }
+
+    #[test]
+    fn control_keys_go_through_the_keymap_in_normal_mode() {
+        let mut state = ViState::default();
+        state.key(KeyCode::Esc, KeyModifiers::NONE);
+
+        assert_eq!(
+            state.key(KeyCode::Char('w'), KeyModifiers::CONTROL),
+            ViKey::Keymap
+        );
+        assert_eq!(state.key(KeyCode::Enter, KeyModifiers::NONE), ViKey::Keymap);
+        assert_eq!(state.mode(), ViMode::Normal);
+    }
+}
@@ -1,11 +1,34 @@
+mod completion;
 pub(crate) mod history;
 mod input;
 mod persistence;
+mod terminal;
+mod width;
 
+pub use completion::{
+    token_span, ClapCompleter, Completer, Completion, CompletionStyle, StaticCompleter,
+};
+pub use crossterm::event::{Event, KeyCode, KeyEvent, KeyModifiers};
+pub use history::{EntryMeta, HistoryEntry};
+#[cfg(feature = "serde")]
+pub use input::config::OptionsConfig;
+#[cfg(feature = "jsonl")]
+pub use input::jsonl_backed_inputs;
 pub use input::{
-    file_backed_inputs, in_memory_inputs, params::Params, Input, Inputs, Options, PromptOptions,
+    file_backed_inputs, file_script_inputs, in_memory_inputs,
+    keymap::{Action, KeyCombo, Keymap},
+    params::Params,
+    split::Splitter,
+    EscAction, Input, Inputs, Options, ParseOutcome, PositionQuery, Printer, PromptOptions,
+    RedrawStrategy, SearchMode, Submission, TabBehavior, UiStream,
 };
-pub use persistence::{FileBackend, Noop};
+#[cfg(feature = "jsonl")]
+pub use persistence::JsonlBackend;
+pub use persistence::{FileBackend, HistoryErrorPolicy, Noop};
+pub use terminal::{EventSource, TerminalEvents};
+pub use width::display_width;
 
 pub type FileBackedInputs = Inputs<FileBackend>;
 pub type MemoryBackedInputs = Inputs<Noop>;
+#[cfg(feature = "jsonl")]
+pub type JsonlBackedInputs = Inputs<JsonlBackend>;
@@ -1,73 +1,683 @@
+use crate::history::{EntryMeta, HistoryEntry};
 use bytes::{BufMut, BytesMut};
 use std::fs::{File, OpenOptions};
 use std::io;
 use std::io::{Read, Seek, SeekFrom, Write};
-use std::path::Path;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
 
 pub trait Persistence {
-    fn load(&mut self) -> io::Result<Vec<String>>;
-    fn persist(&mut self, history: &[String]) -> io::Result<()>;
+    fn load(&mut self) -> io::Result<Vec<HistoryEntry>>;
+    fn persist(&mut self, history: &[HistoryEntry]) -> io::Result<()>;
 }
 
 #[derive(Clone, Copy, Eq, PartialEq, Ord, PartialOrd)]
 pub struct Noop;
 
 impl Persistence for Noop {
-    fn load(&mut self) -> io::Result<Vec<String>> {
+    fn load(&mut self) -> io::Result<Vec<HistoryEntry>> {
         Ok(Vec::new())
     }
 
-    fn persist(&mut self, _: &[String]) -> io::Result<()> {
+    fn persist(&mut self, _: &[HistoryEntry]) -> io::Result<()> {
         Ok(())
     }
 }
 
+#[derive(Debug, Default, Clone, Copy, Eq, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(rename_all = "snake_case"))]
+pub enum HistoryErrorPolicy {
+    /// Report the error to the caller.
+    #[default]
+    Fail,
+    /// Print a warning on stderr and carry on with a session-only history.
+    Warn,
+}
+
 #[derive(Debug)]
 pub struct FileBackend {
-    file: File,
+    path: PathBuf,
+    file: Option<File>,
     buffer: BytesMut,
+    policy: HistoryErrorPolicy,
+    extras: Vec<PathBuf>,
+    dedup: bool,
+    // Entries loaded from the extra files, which never get written to the primary one.
+    loaded_extras: Vec<HistoryEntry>,
 }
 
 // Dumb implementation of a file-backed persistence layer.
 impl FileBackend {
     pub fn new(path: impl AsRef<Path>) -> io::Result<Self> {
-        let file = OpenOptions::new()
-            .create(true)
-            .read(true)
-            .write(true)
-            .open(path)?;
+        Self::with_policy(path, HistoryErrorPolicy::default())
+    }
+
+    // A missing file is never an error, it's created on the spot. The policy only decides what
+    // happens when the file exists but can't be opened or decoded.
+    pub fn with_policy(path: impl AsRef<Path>, policy: HistoryErrorPolicy) -> io::Result<Self> {
+        let path = path.as_ref().to_path_buf();
+        let file = open_history(&path, policy)?;
 
         Ok(FileBackend {
+            path,
             file,
             buffer: BytesMut::new(),
+            policy,
+            extras: Vec::new(),
+            dedup: false,
+            loaded_extras: Vec::new(),
         })
     }
+
+    // History shared between several files, a global one and a per-project one for instance.
+    // Entries of the `extras` files come first, file after file, followed by the entries of
+    // `primary`. New entries only get written to `primary`, and missing extra files are skipped.
+    pub fn layered(
+        primary: impl AsRef<Path>,
+        extras: impl IntoIterator<Item = impl AsRef<Path>>,
+    ) -> io::Result<Self> {
+        let mut backend = Self::new(primary)?;
+        backend.extras = extras
+            .into_iter()
+            .map(|path| path.as_ref().to_path_buf())
+            .collect();
+
+        Ok(backend)
+    }
+
+    // Keeps only the last occurrence of entries found several times across the files.
+    pub fn dedup(self) -> Self {
+        Self {
+            dedup: true,
+            ..self
+        }
+    }
+
+    fn load_extra(&self, path: &Path) -> io::Result<Vec<HistoryEntry>> {
+        match std::fs::read_to_string(path) {
+            Ok(buffer) => Ok(parse_entries(&buffer)),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(Vec::new()),
+            Err(e) => {
+                let e = history_error(path, "read", e);
+
+                if self.policy == HistoryErrorPolicy::Fail {
+                    return Err(e);
+                }
+
+                eprintln!("warning: {}, skipping it", e);
+                Ok(Vec::new())
+            }
+        }
+    }
+}
+
+#[cfg(feature = "locking")]
+const LOCK_ATTEMPTS: usize = 10;
+
+#[cfg(feature = "locking")]
+const LOCK_RETRY_DELAY: std::time::Duration = std::time::Duration::from_millis(50);
+
+// Holds an advisory lock on the history file while `action` runs, so sessions sharing the same
+// file can't interleave their writes. If another process keeps the lock for too long, we give up
+// with a `WouldBlock` error instead of waiting forever.
+#[cfg(feature = "locking")]
+fn locked<A>(file: &mut File, action: impl FnOnce(&mut File) -> io::Result<A>) -> io::Result<A> {
+    use fs2::FileExt;
+
+    let mut attempts = 0;
+
+    loop {
+        match file.try_lock_exclusive() {
+            Ok(()) => break,
+            Err(e) if e.kind() == fs2::lock_contended_error().kind() => {
+                attempts += 1;
+
+                if attempts == LOCK_ATTEMPTS {
+                    return Err(io::Error::new(
+                        io::ErrorKind::WouldBlock,
+                        "history file is locked by another process",
+                    ));
+                }
+
+                std::thread::sleep(LOCK_RETRY_DELAY);
+            }
+            Err(e) => return Err(e),
+        }
+    }
+
+    let result = action(file);
+    file.unlock()?;
+
+    result
+}
+
+#[cfg(not(feature = "locking"))]
+fn locked<A>(file: &mut File, action: impl FnOnce(&mut File) -> io::Result<A>) -> io::Result<A> {
+    action(file)
+}
+
+// A missing file is created. When the file can't be opened, the history is kept for the session
+// only if the policy allows it.
+fn open_history(path: &Path, policy: HistoryErrorPolicy) -> io::Result<Option<File>> {
+    match OpenOptions::new()
+        .create(true)
+        .read(true)
+        .write(true)
+        .truncate(false)
+        .open(path)
+    {
+        Ok(file) => Ok(Some(file)),
+        Err(e) => {
+            let e = history_error(path, "open", e);
+
+            if policy == HistoryErrorPolicy::Fail {
+                return Err(e);
+            }
+
+            eprintln!("warning: {}, history won't be saved", e);
+            Ok(None)
+        }
+    }
+}
+
+fn history_error(path: &Path, action: &str, e: io::Error) -> io::Error {
+    io::Error::new(
+        e.kind(),
+        format!(
+            "failed to {} history file '{}': {}",
+            action,
+            path.display(),
+            e
+        ),
+    )
 }
 
 impl Persistence for FileBackend {
-    fn load(&mut self) -> io::Result<Vec<String>> {
+    fn load(&mut self) -> io::Result<Vec<HistoryEntry>> {
         let mut buffer = String::new();
-        let mut history = Vec::new();
+        let mut extras = Vec::new();
+
+        for path in self.extras.iter() {
+            extras.extend(self.load_extra(path)?);
+        }
+
+        if self.dedup {
+            extras = dedup(extras);
+        }
+
+        self.loaded_extras = extras.clone();
+
+        let mut history = extras;
 
-        self.file.read_to_string(&mut buffer)?;
+        let file = if let Some(file) = self.file.as_mut() {
+            file
+        } else {
+            return Ok(history);
+        };
 
-        for line in buffer.lines() {
-            history.push(line.to_string());
+        if let Err(e) = locked(file, |file| file.read_to_string(&mut buffer)) {
+            let e = history_error(&self.path, "read", e);
+
+            if self.policy == HistoryErrorPolicy::Fail {
+                return Err(e);
+            }
+
+            // We let go of the file so the unreadable content doesn't get overwritten.
+            eprintln!("warning: {}, starting with an empty history", e);
+            self.file = None;
+
+            return Ok(history);
+        }
+
+        let primary = parse_entries(&buffer);
+
+        if !self.dedup {
+            history.extend(primary);
+            return Ok(history);
         }
 
+        // Entries of the extra files also found in the primary one are dropped, the primary file
+        // being the most recent.
+        self.loaded_extras
+            .retain(|extra| !primary.iter().any(|entry| entry.line == extra.line));
+
+        let mut history = self.loaded_extras.clone();
+        history.extend(dedup(primary));
+
         Ok(history)
     }
 
-    fn persist(&mut self, history: &[String]) -> io::Result<()> {
-        self.file.seek(SeekFrom::Start(0))?;
+    fn persist(&mut self, history: &[HistoryEntry]) -> io::Result<()> {
+        let history = &history[self.extras_len(history)..];
+        let file = if let Some(file) = self.file.as_mut() {
+            file
+        } else {
+            return Ok(());
+        };
+
+        self.buffer.extend_from_slice(FORMAT_HEADER.as_bytes());
+        self.buffer.put_u8(b'\n');
 
-        for line in history.iter() {
-            self.buffer.extend_from_slice(line.as_bytes());
+        for entry in history.iter() {
+            if let Some(meta) = entry.meta {
+                self.buffer.extend_from_slice(format_meta(meta).as_bytes());
+                self.buffer.put_u8(b'\n');
+            }
+
+            self.buffer
+                .extend_from_slice(escape(&entry.line).as_bytes());
             self.buffer.put_u8(b'\n');
         }
 
-        self.file.write_all(self.buffer.split().freeze().as_ref())?;
+        let bytes = self.buffer.split().freeze();
 
-        Ok(())
+        locked(file, |file| {
+            file.seek(SeekFrom::Start(0))?;
+            file.write_all(bytes.as_ref())?;
+            file.set_len(bytes.len() as u64)
+        })
+    }
+}
+
+impl FileBackend {
+    // Number of entries at the start of `history` coming from the extra files. The oldest ones
+    // may have been dropped since loading them.
+    fn extras_len(&self, history: &[HistoryEntry]) -> usize {
+        let extras = &self.loaded_extras;
+
+        (0..=extras.len())
+            .map(|skipped| &extras[skipped..])
+            .find(|remaining| {
+                remaining.len() <= history.len()
+                    && remaining
+                        .iter()
+                        .zip(history)
+                        .all(|(extra, entry)| extra.line == entry.line)
+            })
+            .map_or(0, <[HistoryEntry]>::len)
+    }
+}
+
+// First row of the files written in the current format. Files without it predate multiline
+// entries and get loaded the way they were written, one entry per row.
+const FORMAT_HEADER: &str = "#glyph-history v2";
+
+// Multiline entries are stored one line per row, every row but the last one ending with a
+// backslash. Backslashes of the entries themselves are doubled, so a row ending with an odd
+// number of them is the only one going on. An entry may be preceded by a row holding its metadata,
+// entries starting with `#` getting a backslash in front so they never pass for one.
+fn parse_entries(buffer: &str) -> Vec<HistoryEntry> {
+    let mut lines = buffer.lines();
+
+    if lines.next() != Some(FORMAT_HEADER) {
+        return buffer.lines().map(HistoryEntry::new).collect();
+    }
+
+    let mut history = Vec::new();
+    let mut entry = String::new();
+    let mut meta = None;
+
+    for line in lines {
+        if entry.is_empty() {
+            if let Some(parsed) = parse_meta(line) {
+                meta = Some(parsed);
+                continue;
+            }
+        }
+
+        let backslashes = line.len() - line.trim_end_matches('\\').len();
+
+        if backslashes % 2 == 1 {
+            entry.push_str(&line[..line.len() - 1]);
+            entry.push('\n');
+        } else {
+            entry.push_str(line);
+            history.push(HistoryEntry {
+                meta: meta.take(),
+                ..HistoryEntry::new(unescape(&std::mem::take(&mut entry)))
+            });
+        }
+    }
+
+    if let Some(entry) = entry.strip_suffix('\n') {
+        history.push(HistoryEntry {
+            meta,
+            ..HistoryEntry::new(unescape(entry))
+        });
+    }
+
+    history
+}
+
+fn escape(line: &str) -> String {
+    let escaped = line.replace('\\', "\\\\").replace('\n', "\\\n");
+
+    if escaped.starts_with('#') {
+        format!("\\{}", escaped)
+    } else {
+        escaped
+    }
+}
+
+// Undoes `escape`, line breaks aside. A backslash escaping nothing known is kept as is.
+fn unescape(raw: &str) -> String {
+    let mut line = String::with_capacity(raw.len());
+    let mut chars = raw.chars();
+
+    while let Some(c) = chars.next() {
+        match (c, chars.clone().next()) {
+            ('\\', Some(escaped @ ('\\' | '#'))) => {
+                chars.next();
+                line.push(escaped);
+            }
+
+            (c, _) => line.push(c),
+        }
+    }
+
+    line
+}
+
+// Keeps the last occurrence of every entry, in order.
+fn dedup(entries: Vec<HistoryEntry>) -> Vec<HistoryEntry> {
+    let mut kept = Vec::with_capacity(entries.len());
+
+    for (idx, entry) in entries.iter().enumerate() {
+        if !entries[idx + 1..]
+            .iter()
+            .any(|later| later.line == entry.line)
+        {
+            kept.push(entry.clone());
+        }
+    }
+
+    kept
+}
+
+// Metadata rows look like `#+<duration in milliseconds> <ok|failed>`. Only files starting with
+// `FORMAT_HEADER` have them.
+fn format_meta(meta: EntryMeta) -> String {
+    let outcome = if meta.success { "ok" } else { "failed" };
+
+    format!("#+{} {}", meta.duration.as_millis(), outcome)
+}
+
+fn parse_meta(line: &str) -> Option<EntryMeta> {
+    let (millis, outcome) = line.strip_prefix("#+")?.split_once(' ')?;
+    let success = match outcome {
+        "ok" => true,
+        "failed" => false,
+        _ => return None,
+    };
+
+    Some(EntryMeta {
+        duration: Duration::from_millis(millis.parse().ok()?),
+        success,
+    })
+}
+
+/// Stores every entry as a JSON object on its own line, along with the time it got stored.
+#[cfg(feature = "jsonl")]
+#[derive(Debug)]
+pub struct JsonlBackend {
+    path: PathBuf,
+    file: Option<File>,
+    policy: HistoryErrorPolicy,
+}
+
+// One line of a JSON-lines history file. The timestamp counts seconds since the Unix epoch, in UTC.
+#[cfg(feature = "jsonl")]
+#[derive(serde::Serialize, serde::Deserialize)]
+struct JsonlRecord {
+    line: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    timestamp: Option<u64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    duration_ms: Option<u64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    success: Option<bool>,
+}
+
+#[cfg(feature = "jsonl")]
+impl JsonlBackend {
+    pub fn new(path: impl AsRef<Path>) -> io::Result<Self> {
+        Self::with_policy(path, HistoryErrorPolicy::default())
+    }
+
+    pub fn with_policy(path: impl AsRef<Path>, policy: HistoryErrorPolicy) -> io::Result<Self> {
+        let path = path.as_ref().to_path_buf();
+        let file = open_history(&path, policy)?;
+
+        Ok(JsonlBackend { path, file, policy })
+    }
+}
+
+#[cfg(feature = "jsonl")]
+impl Persistence for JsonlBackend {
+    // Lines that aren't a valid record are skipped, the rest of the history still loading.
+    fn load(&mut self) -> io::Result<Vec<HistoryEntry>> {
+        use std::time::UNIX_EPOCH;
+
+        let mut buffer = String::new();
+        let file = if let Some(file) = self.file.as_mut() {
+            file
+        } else {
+            return Ok(Vec::new());
+        };
+
+        if let Err(e) = locked(file, |file| file.read_to_string(&mut buffer)) {
+            let e = history_error(&self.path, "read", e);
+
+            if self.policy == HistoryErrorPolicy::Fail {
+                return Err(e);
+            }
+
+            eprintln!("warning: {}, starting with an empty history", e);
+            self.file = None;
+
+            return Ok(Vec::new());
+        }
+
+        let history = buffer
+            .lines()
+            .filter_map(|line| serde_json::from_str::<JsonlRecord>(line).ok())
+            .map(|record| HistoryEntry {
+                meta: record
+                    .duration_ms
+                    .zip(record.success)
+                    .map(|(millis, success)| EntryMeta {
+                        duration: Duration::from_millis(millis),
+                        success,
+                    }),
+                timestamp: record
+                    .timestamp
+                    .map(|secs| UNIX_EPOCH + Duration::from_secs(secs)),
+                ..HistoryEntry::new(record.line)
+            })
+            .collect();
+
+        Ok(history)
+    }
+
+    fn persist(&mut self, history: &[HistoryEntry]) -> io::Result<()> {
+        use std::time::UNIX_EPOCH;
+
+        let file = if let Some(file) = self.file.as_mut() {
+            file
+        } else {
+            return Ok(());
+        };
+
+        let mut buffer = String::new();
+
+        for entry in history {
+            let record = JsonlRecord {
+                line: entry.line.clone(),
+                timestamp: entry
+                    .timestamp
+                    .and_then(|time| time.duration_since(UNIX_EPOCH).ok())
+                    .map(|elapsed| elapsed.as_secs()),
+                duration_ms: entry.meta.map(|meta| meta.duration.as_millis() as u64),
+                success: entry.meta.map(|meta| meta.success),
+            };
+
+            buffer.push_str(&serde_json::to_string(&record).map_err(io::Error::from)?);
+            buffer.push('\n');
+        }
+
+        locked(file, |file| {
+            file.seek(SeekFrom::Start(0))?;
+            file.write_all(buffer.as_bytes())?;
+            file.set_len(buffer.len() as u64)
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // A fresh path in the temporary directory, unique to the calling test.
+    fn scratch(name: &str) -> PathBuf {
+        let path = std::env::temp_dir().join(format!("glyph-test-{}-{}", std::process::id(), name));
+        let _ = std::fs::remove_file(&path);
+        path
+    }
+
+    fn round_trip(name: &str, lines: &[&str]) -> Vec<String> {
+        let path = scratch(name);
+        let entries = lines
+            .iter()
+            .map(|line| HistoryEntry::new(*line))
+            .collect::<Vec<_>>();
+
+        FileBackend::new(&path).unwrap().persist(&entries).unwrap();
+        let loaded = FileBackend::new(&path).unwrap().load().unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        loaded.into_iter().map(|entry| entry.line).collect()
+    }
+
+    #[test]
+    fn trailing_backslashes_dont_join_entries() {
+        assert_eq!(
+            round_trip("backslash", &["echo a\\", "next"]),
+            ["echo a\\", "next"]
+        );
+    }
+
+    #[test]
+    fn multiline_entries_survive_a_round_trip() {
+        let lines = ["first\nsecond", "a\\\nb\\\\", "\n", "plain"];
+
+        assert_eq!(round_trip("multiline", &lines), lines);
+    }
+
+    #[test]
+    fn lines_looking_like_metadata_stay_entries() {
+        let lines = ["#+5 ok", "plain", "\\#+5 ok", "# comment"];
+
+        assert_eq!(round_trip("meta-lookalike", &lines), lines);
+    }
+
+    #[test]
+    fn metadata_survives_a_round_trip() {
+        let path = scratch("meta");
+        let meta = EntryMeta {
+            duration: Duration::from_millis(42),
+            success: false,
+        };
+        let entries = [
+            HistoryEntry {
+                meta: Some(meta),
+                ..HistoryEntry::new("#+1 ok")
+            },
+            HistoryEntry::new("plain"),
+        ];
+
+        FileBackend::new(&path).unwrap().persist(&entries).unwrap();
+        let loaded = FileBackend::new(&path).unwrap().load().unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        assert_eq!(loaded, entries);
+    }
+
+    #[test]
+    fn files_without_the_header_hold_one_entry_per_row() {
+        let path = scratch("legacy");
+        std::fs::write(&path, "dir C:\\tmp\necho a\\\nnext\na\\\\b\n").unwrap();
+
+        let mut backend = FileBackend::new(&path).unwrap();
+        let loaded = backend.load().unwrap();
+        let lines = loaded
+            .iter()
+            .map(|entry| entry.line.as_str())
+            .collect::<Vec<_>>();
+
+        assert_eq!(lines, ["dir C:\\tmp", "echo a\\", "next", "a\\\\b"]);
+
+        // Once rewritten in the current format, the entries load back unchanged.
+        backend.persist(&loaded).unwrap();
+        let reloaded = FileBackend::new(&path).unwrap().load().unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        assert_eq!(reloaded, loaded);
+    }
+
+    #[test]
+    fn files_without_the_header_have_no_metadata_rows() {
+        let loaded = parse_entries("#+5 ok\nls\n#+12 failed\n");
+
+        assert_eq!(
+            loaded,
+            [
+                HistoryEntry::new("#+5 ok"),
+                HistoryEntry::new("ls"),
+                HistoryEntry::new("#+12 failed"),
+            ]
+        );
+    }
+
+    #[cfg(feature = "jsonl")]
+    #[test]
+    fn jsonl_entries_survive_a_round_trip() {
+        use std::time::UNIX_EPOCH;
+
+        let path = scratch("round-trip.jsonl");
+        let entries = [
+            HistoryEntry {
+                meta: Some(EntryMeta {
+                    duration: Duration::from_millis(1500),
+                    success: true,
+                }),
+                timestamp: Some(UNIX_EPOCH + Duration::from_secs(1_700_000_000)),
+                ..HistoryEntry::new("select \"a\"\nfrom b\\")
+            },
+            HistoryEntry::new("#+5 ok"),
+        ];
+
+        JsonlBackend::new(&path).unwrap().persist(&entries).unwrap();
+        let loaded = JsonlBackend::new(&path).unwrap().load().unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        assert_eq!(loaded, entries);
+    }
+
+    #[cfg(feature = "jsonl")]
+    #[test]
+    fn malformed_jsonl_lines_are_skipped() {
+        let path = scratch("malformed.jsonl");
+        let content = "{\"line\":\"first\"}\nnot json\n{\"text\":1}\n{\"line\":\"second\"}\n";
+        std::fs::write(&path, content).unwrap();
+
+        let loaded = JsonlBackend::new(&path).unwrap().load().unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        let lines = loaded
+            .iter()
+            .map(|entry| entry.line.as_str())
+            .collect::<Vec<_>>();
+        assert_eq!(lines, ["first", "second"]);
     }
 }
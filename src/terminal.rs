@@ -0,0 +1,343 @@
+use crossterm::event::{self, Event};
+use crossterm::style::{Attribute, SetAttribute};
+use crossterm::terminal::LeaveAlternateScreen;
+use crossterm::{cursor, queue, terminal};
+use std::io::{self, BufWriter, Write};
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::time::Duration;
+
+/// Where `Inputs` gets its events from. The real terminal is used by default, but any source can
+/// be plugged in with `Inputs::with_io`, a PTY or a test harness for instance.
+pub trait EventSource {
+    /// Tells whether an event is available within `timeout`.
+    fn poll(&mut self, timeout: Duration) -> io::Result<bool>;
+
+    /// Waits for the next event.
+    fn read(&mut self) -> io::Result<Event>;
+
+    /// Size of the terminal, as `(columns, rows)`.
+    fn size(&mut self) -> io::Result<(u16, u16)> {
+        terminal::size()
+    }
+
+    /// Position of the cursor, as `(column, row)`.
+    fn cursor_position(&mut self) -> io::Result<(u16, u16)> {
+        cursor::position()
+    }
+}
+
+/// Events coming from the terminal glyph runs in.
+#[derive(Debug, Default, Clone, Copy)]
+pub struct TerminalEvents;
+
+impl EventSource for TerminalEvents {
+    fn poll(&mut self, timeout: Duration) -> io::Result<bool> {
+        event::poll(timeout)
+    }
+
+    fn read(&mut self) -> io::Result<Event> {
+        event::read()
+    }
+}
+
+// Where the cursor position question is written to, or its answer read from.
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub(crate) enum Channel {
+    Stdout,
+    Stderr,
+    Tty,
+}
+
+// Events of the terminal, the cursor position being asked on `ask` and answered on `answer`.
+// crossterm always asks on stdout and reads the answer from stdin, which is wrong as soon as the
+// UI isn't drawn on the terminal stdin belongs to.
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct QueryEvents {
+    pub(crate) ask: Channel,
+    pub(crate) answer: Channel,
+}
+
+impl EventSource for QueryEvents {
+    fn poll(&mut self, timeout: Duration) -> io::Result<bool> {
+        event::poll(timeout)
+    }
+
+    fn read(&mut self) -> io::Result<Event> {
+        event::read()
+    }
+
+    #[cfg(unix)]
+    fn cursor_position(&mut self) -> io::Result<(u16, u16)> {
+        let raw = terminal::is_raw_mode_enabled()?;
+
+        if !raw {
+            terminal::enable_raw_mode()?;
+        }
+
+        let position = query_cursor_position(self.ask, self.answer);
+
+        if !raw {
+            terminal::disable_raw_mode()?;
+        }
+
+        position
+    }
+}
+
+#[cfg(unix)]
+fn open_channel(channel: Channel) -> io::Result<std::fs::File> {
+    use std::os::fd::AsFd;
+
+    let fd = match channel {
+        Channel::Stdout => io::stdout().as_fd().try_clone_to_owned()?,
+        Channel::Stderr => io::stderr().as_fd().try_clone_to_owned()?,
+        Channel::Tty => {
+            return std::fs::OpenOptions::new()
+                .read(true)
+                .write(true)
+                .open("/dev/tty")
+        }
+    };
+
+    Ok(fd.into())
+}
+
+// Asks the terminal where the cursor is and reads the `ESC [ row ; column R` answer directly,
+// bypassing crossterm. Keys typed in the meantime are dropped.
+#[cfg(unix)]
+fn query_cursor_position(ask: Channel, answer: Channel) -> io::Result<(u16, u16)> {
+    use std::io::Read;
+
+    let mut question = open_channel(ask)?;
+    let mut reader = open_channel(answer)?;
+    let mut reply = Vec::new();
+    let mut byte = [0u8];
+
+    question.write_all(b"\x1b[6n")?;
+    question.flush()?;
+
+    loop {
+        reader.read_exact(&mut byte)?;
+
+        match byte[0] {
+            b'\x1b' => reply.clear(),
+            b'R' if reply.starts_with(b"[") => break,
+            b => reply.push(b),
+        }
+    }
+
+    let reply = String::from_utf8_lossy(&reply[1..]);
+    let invalid = || io::Error::new(io::ErrorKind::InvalidData, "invalid cursor position reply");
+    let (row, column) = reply.split_once(';').ok_or_else(invalid)?;
+    let row = row.parse::<u16>().map_err(|_| invalid())?;
+    let column = column.parse::<u16>().map_err(|_| invalid())?;
+
+    Ok((column.saturating_sub(1), row.saturating_sub(1)))
+}
+
+// Switches the terminal in and out of raw mode. These are crossterm's, unless a test plugs in its
+// own to see them flipped without a terminal.
+#[derive(Clone, Copy)]
+pub(crate) struct RawMode {
+    pub(crate) enable: fn() -> io::Result<()>,
+    pub(crate) disable: fn() -> io::Result<()>,
+}
+
+impl Default for RawMode {
+    fn default() -> Self {
+        Self {
+            enable: terminal::enable_raw_mode,
+            disable: terminal::disable_raw_mode,
+        }
+    }
+}
+
+// Puts the terminal back to normal when dropped, however reading the input ends, errors and panics
+// included. Raw mode is left through `raw_mode`, if any, the caller managing it otherwise. Unless
+// the read completed, whatever it may have left behind goes as well: text attributes, the hidden
+// cursor and the alternate screen.
+pub(crate) struct TerminalGuard {
+    raw_mode: Option<RawMode>,
+    out: Output,
+    alternate_screen: bool,
+    completed: bool,
+}
+
+impl TerminalGuard {
+    pub(crate) fn new(raw_mode: Option<RawMode>, out: Output, alternate_screen: bool) -> Self {
+        Self {
+            raw_mode,
+            out,
+            alternate_screen,
+            completed: false,
+        }
+    }
+
+    // The read ended normally, the screen being left as it is.
+    pub(crate) fn complete(mut self) {
+        self.completed = true;
+    }
+}
+
+impl Drop for TerminalGuard {
+    fn drop(&mut self) {
+        if !self.completed {
+            let _ = queue!(self.out, SetAttribute(Attribute::Reset), cursor::Show);
+
+            if self.alternate_screen {
+                let _ = queue!(self.out, LeaveAlternateScreen);
+            }
+
+            let _ = self.out.flush();
+        }
+
+        if let Some(raw_mode) = self.raw_mode {
+            let _ = (raw_mode.disable)();
+        }
+    }
+}
+
+// Shared handle on the event source, cheap to clone so it can be moved into closures the same way
+// `io::stdin()` would be. It's `Send`, so `Inputs` can be moved to another thread.
+#[derive(Clone)]
+pub(crate) struct Events(Arc<Mutex<dyn EventSource + Send>>);
+
+impl Events {
+    pub(crate) fn new(source: impl EventSource + Send + 'static) -> Self {
+        Self(Arc::new(Mutex::new(source)))
+    }
+
+    // A panic while reading an event doesn't make the source any less usable.
+    fn source(&self) -> MutexGuard<'_, dyn EventSource + Send + 'static> {
+        self.0.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    pub(crate) fn poll(&self, timeout: Duration) -> io::Result<bool> {
+        self.source().poll(timeout)
+    }
+
+    pub(crate) fn read(&self) -> io::Result<Event> {
+        self.source().read()
+    }
+
+    pub(crate) fn size(&self) -> io::Result<(u16, u16)> {
+        self.source().size()
+    }
+
+    pub(crate) fn cursor_position(&self) -> io::Result<(u16, u16)> {
+        self.source().cursor_position()
+    }
+}
+
+// Shared handle on the writer everything gets printed to, used like `io::stdout()`. Writes are
+// buffered until the next flush, so drawing the outcome of a key reaches the terminal in one go
+// instead of a write per escape sequence or per line.
+#[derive(Clone)]
+pub(crate) struct Output {
+    writer: Arc<Mutex<BufWriter<Box<dyn Write + Send>>>>,
+    crlf: bool,
+}
+
+impl Output {
+    pub(crate) fn new(writer: impl Write + Send + 'static) -> Self {
+        Self {
+            writer: Arc::new(Mutex::new(BufWriter::new(Box::new(writer)))),
+            crlf: false,
+        }
+    }
+
+    fn writer(&self) -> MutexGuard<'_, BufWriter<Box<dyn Write + Send>>> {
+        self.writer.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    // Writes every newline as a carriage return followed by a newline, which the terminal no
+    // longer does by itself once in raw mode.
+    pub(crate) fn crlf(self) -> Self {
+        Self { crlf: true, ..self }
+    }
+}
+
+impl Write for Output {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let mut writer = self.writer();
+
+        if !self.crlf {
+            return writer.write(buf);
+        }
+
+        for (idx, line) in buf.split(|b| *b == b'\n').enumerate() {
+            if idx > 0 {
+                writer.write_all(b"\r\n")?;
+            }
+
+            writer.write_all(line)?;
+        }
+
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.writer().flush()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::panic::{self, AssertUnwindSafe};
+    use std::sync::atomic::{AtomicBool, Ordering};
+
+    #[derive(Clone, Default)]
+    struct Screen(Arc<Mutex<Vec<u8>>>);
+
+    impl Write for Screen {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.0.lock().unwrap().extend_from_slice(buf);
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn the_guard_restores_the_terminal_on_panic() {
+        static RAW: AtomicBool = AtomicBool::new(true);
+
+        fn enable() -> io::Result<()> {
+            RAW.store(true, Ordering::SeqCst);
+            Ok(())
+        }
+
+        fn disable() -> io::Result<()> {
+            RAW.store(false, Ordering::SeqCst);
+            Ok(())
+        }
+
+        let screen = Screen::default();
+        let raw_mode = RawMode { enable, disable };
+
+        let result = panic::catch_unwind(AssertUnwindSafe(|| {
+            let _guard = TerminalGuard::new(Some(raw_mode), Output::new(screen.clone()), true);
+            panic!("forced panic");
+        }));
+
+        assert!(result.is_err());
+        assert!(!RAW.load(Ordering::SeqCst), "raw mode left");
+
+        let written = String::from_utf8(screen.0.lock().unwrap().clone()).unwrap();
+        assert!(written.contains("\x1b[0m"), "attributes reset");
+        assert!(written.contains("\x1b[?25h"), "cursor shown");
+        assert!(written.contains("\x1b[?1049l"), "alternate screen left");
+    }
+
+    #[test]
+    fn a_completed_read_leaves_the_screen_alone() {
+        let screen = Screen::default();
+
+        TerminalGuard::new(None, Output::new(screen.clone()), true).complete();
+
+        assert!(screen.0.lock().unwrap().is_empty());
+    }
+}
@@ -0,0 +1,146 @@
+use std::iter::Peekable;
+use std::str::Chars;
+use unicode_width::UnicodeWidthChar;
+
+/// Number of terminal cells `text` occupies once printed, East Asian wide characters and most emoji
+/// taking two. ANSI escape sequences, control characters and combining marks don't take any room
+/// on screen so they are skipped.
+pub fn display_width(text: &str) -> usize {
+    let mut chars = text.chars().peekable();
+    let mut width = 0;
+
+    while let Some(c) = chars.next() {
+        if c == '\x1b' {
+            skip_escape_sequence(&mut chars);
+            continue;
+        }
+
+        width += char_width(c);
+    }
+
+    width
+}
+
+// Cells `c` occupies, none for control characters and combining marks.
+pub(crate) fn char_width(c: char) -> usize {
+    if c.is_control() {
+        0
+    } else {
+        c.width().unwrap_or(0)
+    }
+}
+
+// `text` without the escape sequences and control characters `display_width` leaves out, so what
+// gets printed occupies exactly that many cells.
+pub(crate) fn strip_escapes(text: &str) -> String {
+    let mut chars = text.chars().peekable();
+    let mut stripped = String::with_capacity(text.len());
+
+    while let Some(c) = chars.next() {
+        if c == '\x1b' {
+            skip_escape_sequence(&mut chars);
+            continue;
+        }
+
+        if !c.is_control() {
+            stripped.push(c);
+        }
+    }
+
+    stripped
+}
+
+// `text` with its control characters written the way terminals echo them, `^C` for Ctrl-C, `^[`
+// for Escape or `^?` for Delete, each of them taking two cells.
+pub(crate) fn caret_notation(text: &str) -> String {
+    let mut shown = String::with_capacity(text.len());
+
+    for c in text.chars() {
+        match c {
+            '\x00'..='\x1f' => {
+                shown.push('^');
+                shown.push((c as u8 + b'@') as char);
+            }
+
+            '\x7f' => shown.push_str("^?"),
+            _ => shown.push(c),
+        }
+    }
+
+    shown
+}
+
+fn skip_escape_sequence(chars: &mut Peekable<Chars>) {
+    match chars.next() {
+        // CSI: parameters and intermediate bytes, terminated by a byte in the `@`..=`~` range.
+        Some('[') => {
+            for c in chars.by_ref() {
+                if ('@'..='~').contains(&c) {
+                    break;
+                }
+            }
+        }
+
+        // OSC: terminated either by BEL or by ST (ESC \).
+        Some(']') => {
+            while let Some(c) = chars.next() {
+                if c == '\x07' {
+                    break;
+                }
+
+                if c == '\x1b' && chars.peek() == Some(&'\\') {
+                    chars.next();
+                    break;
+                }
+            }
+        }
+
+        _ => {}
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn ascii_takes_a_cell_per_character() {
+        assert_eq!(display_width(""), 0);
+        assert_eq!(display_width("hello, world"), 12);
+        assert_eq!(char_width('a'), 1);
+    }
+
+    #[test]
+    fn wide_characters_take_two_cells() {
+        assert_eq!(display_width("日本語"), 6);
+        assert_eq!(display_width("a你b"), 4);
+        assert_eq!(char_width('你'), 2);
+    }
+
+    #[test]
+    fn combining_marks_and_control_characters_take_no_cell() {
+        // `e` followed by a combining acute accent.
+        assert_eq!(display_width("e\u{301}"), 1);
+        assert_eq!(char_width('\u{301}'), 0);
+        assert_eq!(display_width("a\tb\x07"), 2);
+        assert_eq!(char_width('\x01'), 0);
+    }
+
+    #[test]
+    fn escape_sequences_take_no_cell() {
+        assert_eq!(display_width("\x1b[1;31mred\x1b[0m"), 3);
+        assert_eq!(display_width("\x1b]0;title\x07日本"), 4);
+        assert_eq!(
+            display_width("\x1b]8;;https://a.b\x1b\\link\x1b]8;;\x1b\\"),
+            4
+        );
+    }
+
+    #[test]
+    fn stripping_leaves_what_display_width_counts() {
+        let text = "\x1b[31m日本\x1b[0m e\u{301}\x1b]0;title\x07\tok";
+
+        assert_eq!(strip_escapes(text), "日本 e\u{301}ok");
+        assert_eq!(display_width(&strip_escapes(text)), display_width(text));
+    }
+}